@@ -1,12 +1,21 @@
 use macroquad::prelude::*;
-use ::rand::{thread_rng, Rng};
-use std::cmp::{min, max};
+use ::rand::rngs::StdRng;
+use ::rand::seq::SliceRandom;
+use ::rand::{Rng, SeedableRng};
+use std::cmp::{min, max, Reverse};
 
-use std::collections::HashMap;
-use std::io::Cursor;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{Cursor, ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
 use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
-use rodio::source::Source;
+use rodio::source::{SineWave, Source};
 
 // -------------------------------------------------------------------
 // Audio assets embedded into the binary.
@@ -15,27 +24,126 @@ const MUSIC_A: &[u8] = include_bytes!("../resources/music/music-a.mp3");
 const MUSIC_B: &[u8] = include_bytes!("../resources/music/music-b.mp3");
 
 const MUSIC_LIST: [&[u8]; 3] = [MUSIC_A_GB, MUSIC_A, MUSIC_B];
+// Extra songs are picked up from here at startup.
+const CUSTOM_MUSIC_DIR: &str = "resources/music";
+const MUSIC_EXTENSIONS: [&str; 3] = ["mp3", "ogg", "wav"];
+const DEFAULT_MUSIC_VOLUME: f32 = 0.5;
+const DEFAULT_SFX_VOLUME: f32 = 0.5;
+const MUSIC_FADE_SECONDS: f32 = 0.5;
+const MUSIC_VOLUME_STEP: f32 = 0.1;
+const VOLUME_DISPLAY_DURATION: f32 = 1.0;
+// Rising arpeggio (frequency in Hz, seconds) played when a run passes the saved high score.
+const HIGH_SCORE_JINGLE: [(f32, f32); 4] = [(523.25, 0.1), (659.25, 0.1), (783.99, 0.1), (1046.5, 0.3)];
+const SFX_VOLUME: f32 = 0.2; // Sine tones are loud next to the music, so keep them quieter.
+// Line clears climb this arpeggio one note per cleared line, so a Tetris plays all four.
+const LINE_CLEAR_NOTES: [f32; 4] = [523.25, 659.25, 783.99, 1046.5];
+const LINE_CLEAR_NOTE_SECONDS: f32 = 0.06;
+const COMBO_SFX_MAX_STEPS: i32 = 12; // Each combo step raises the clear a semitone, up to an octave.
 
 // -------------------------------------------------------------------
 // Game constants
+// Standard playfield size; see BoardSize for the alternatives.
 const GRID_WIDTH: usize = 10;
 const GRID_HEIGHT: usize = 20;
 const TILE_SIZE: f32 = 30.0;
 const PREVIEW_TILE_SIZE: f32 = 25.0;
+const QUEUE_TILE_SIZE: f32 = 18.0;
+const QUEUE_SPACING: f32 = 45.0;
+const PREVIEW_BOX_SIZE: f32 = 50.0;
+
+// Reference layout that draw() scales to the window. The board keeps
+// LAYOUT_BOTTOM_MARGIN free below it for the controls text.
+const LAYOUT_BASE_HEIGHT: f32 = 700.0;
+const LAYOUT_MIN_WIDTH: f32 = 900.0;
+const LAYOUT_BOTTOM_MARGIN: f32 = 100.0;
+const PANEL_GAP: f32 = 60.0;
+const LEFT_PANEL_WIDTH: f32 = 150.0;
+const MIN_LAYOUT_SCALE: f32 = 0.25;
+
+// Window sizes offered on the title screen; the first is the default.
+const WINDOW_PRESETS: [(u32, u32); 4] = [(1410, 700), (1280, 720), (1920, 1080), (2560, 1440)];
+const WINDOW_SAVE_DELAY: f32 = 0.5; // Seconds a manual resize must hold before it's saved.
+
+// How many upcoming pieces are shown under "Next", and the range the profile may set.
+// 1 is the classic single preview; more than 5 would run into the HUD below.
+const DEFAULT_NEXT_QUEUE_SIZE: usize = 5;
+const NEXT_QUEUE_SIZE_RANGE: (usize, usize) = (1, 5);
 
-const FALL_SPEED: f32 = 3.0;
-const SOFT_DROP_SPEED: f32 = 15.0;
-const INITIAL_HORIZONTAL_DELAY: f32 = 0.2;
-const HORIZONTAL_REPEAT_DELAY: f32 = 0.1;
+const DEFAULT_SOFT_DROP_SPEED: f32 = 60.0; // Rows per second while soft dropping: one per frame.
+const SOFT_DROP_SPEED_RANGE: (f32, f32) = (1.0, 600.0);
+const LINES_PER_LEVEL: u32 = 10;
+const TIMED_MODE_SECONDS: f32 = 120.0;
+const SPRINT_LINES: u32 = 40;
+const MARATHON_LINES: u32 = 150;
+const DIG_GARBAGE_ROWS: usize = 10;
+const SURVIVAL_RISE_START: f32 = 8.0; // Seconds between rising garbage rows at the start of Survival.
+const SURVIVAL_RISE_MIN: f32 = 1.5;
+const SURVIVAL_RISE_SPEEDUP: f32 = 0.02; // Seconds shaved off the rise interval per second survived.
+const SURVIVAL_ROW_POINTS: u32 = 100; // Per garbage row survived, times the level.
+const DEFAULT_INVISIBLE_DELAY: f32 = 1.0;
+const DEFAULT_GHOST_OPACITY: f32 = 0.3;
+const ZEN_CLEAR_ROWS: usize = 8; // Rows wiped from the top when a Zen stack reaches the spawn.
+const PRACTICE_UNDO_DEPTH: usize = 50; // Locks Practice mode can take back.
+const DEFAULT_DANGER_HEIGHT: usize = 12; // Stack height, in standard rows, that turns on the danger effect.
+const DANGER_PULSE_SPEED: f32 = 6.0; // Radians per second for the danger vignette's pulse.
+const ULTRA_MODE_SECONDS: f32 = 120.0;
+const ULTRA_LEVEL: u32 = 5;
+const ENDLESS_SPEEDUP_SECONDS: f32 = 60.0; // Endless gravity doubles this often on top of the level speed.
+const MAX_GRAVITY_ROWS_PER_FRAME: f32 = 20.0; // 20G: the piece falls straight to the floor.
+const DEFAULT_DAS: f32 = 0.2;
+const DEFAULT_ARR: f32 = 0.1;
+const LOCK_DELAY: f32 = 0.5;
+const LINE_CLEAR_DURATION: f32 = 0.27;
+const HARD_DROP_DOUBLE_TAP_WINDOW: f32 = 0.3; // Seconds to tap hard drop again when confirming drops.
+const LOCK_FLASH_DURATION: f32 = 0.2;
+const SQUARE_POPUP_DURATION: f32 = 1.5;
+const SQUARE_POPUP_RISE: f32 = 2.0; // Cells the points text floats up.
+const PARTICLES_PER_CELL: usize = 3;
+const MAX_PARTICLES: usize = 300;
+const PARTICLE_LIFETIME: f32 = 0.8;
+const PARTICLE_GRAVITY: f32 = 30.0; // Cells per second squared.
+const MAX_LOCK_DELAY_RESETS: u32 = 15;
+// The simulation always advances in fixed 60 Hz steps so replays play back identically.
+const SIM_STEP: f32 = 1.0 / 60.0;
+// A frame this long means the window was minimized, dragged, or otherwise stopped drawing,
+// so a running game pauses itself. Ordinary hitches stay well under it.
+const STALL_PAUSE_SECONDS: f32 = 0.75;
+const MAX_SIM_STEPS_PER_FRAME: u32 = 5;
 
 const GAME_AREA_COLOR: Color = Color::new(0.2, 0.2, 0.2, 1.0);
+const GRID_LINE_COLOR: Color = Color::new(0.3, 0.3, 0.3, 1.0);
 const BLACK_COLOR: Color = BLACK;
 const GOLD_COLOR: Color = Color::new(1.0, 0.84, 0.0, 1.0);
 const SILVER_COLOR: Color = Color::new(0.75, 0.75, 0.75, 1.0);
+const GARBAGE_COLOR: Color = Color::new(0.45, 0.45, 0.45, 1.0);
 
+// Base 4x4 square bonuses, scaled by level and difficulty when a square forms.
 const GOLD_POINTS: u32 = 500;
 const SILVER_POINTS: u32 = 200;
 
+// Frames (at 60 fps) per row of gravity for levels 1-25, shaped like the NES
+// table. Every level past the end of the table falls one row per frame.
+const GRAVITY_FRAMES: [u32; 25] = [
+    20, 18, 16, 13, 10, 8, 6, 5, 5, 5,
+    4, 4, 4, 3, 3, 2, 2, 2, 2, 2,
+    2, 2, 2, 2, 2,
+];
+
+// Points for clearing 0-4 rows at once (single, double, triple, tetris), times the level.
+const LINE_CLEAR_POINTS: [u32; 5] = [0, 100, 300, 500, 800];
+// T-spin points per level for 0-3 lines, replacing the normal line clear points.
+const T_SPIN_POINTS: [u32; 4] = [400, 800, 1200, 1600];
+const T_SPIN_POPUP_DURATION: f32 = 1.0;
+const HIGH_SCORE_BANNER_DURATION: f32 = 2.5;
+const STATS_RESET_DISPLAY_DURATION: f32 = 1.5;
+const I_DROUGHT_WARNING: u32 = 12; // Pieces without an I before the drought counter turns red.
+// Tetrises and T-spin clears that follow another one score 1.5x.
+const BACK_TO_BACK_NUMERATOR: u32 = 3;
+const BACK_TO_BACK_DENOMINATOR: u32 = 2;
+const SOFT_DROP_POINTS_PER_CELL: u32 = 1;
+const HARD_DROP_POINTS_PER_CELL: u32 = 2;
+
+// Block color palettes, one entry per piece in TetrominoType order.
 const NES_COLORS: [Color; 7] = [
     Color { r: 0.0,    g: 1.0,    b: 1.0,    a: 1.0 }, // I
     Color { r: 1.0,    g: 1.0,    b: 0.0,    a: 1.0 }, // O
@@ -46,56 +154,284 @@ const NES_COLORS: [Color; 7] = [
     Color { r: 1.0,    g: 0.3334, b: 0.0,    a: 1.0 }, // L
 ];
 
+// Okabe-Ito hues, which stay distinct with red-green color blindness.
+const DEUTERANOPIA_COLORS: [Color; 7] = [
+    Color { r: 0.337,  g: 0.706,  b: 0.914,  a: 1.0 }, // I (sky blue)
+    Color { r: 0.941,  g: 0.894,  b: 0.259,  a: 1.0 }, // O (yellow)
+    Color { r: 0.8,    g: 0.475,  b: 0.655,  a: 1.0 }, // T (reddish purple)
+    Color { r: 0.0,    g: 0.620,  b: 0.451,  a: 1.0 }, // S (bluish green)
+    Color { r: 0.835,  g: 0.369,  b: 0.0,    a: 1.0 }, // Z (vermillion)
+    Color { r: 0.0,    g: 0.447,  b: 0.698,  a: 1.0 }, // J (blue)
+    Color { r: 0.902,  g: 0.624,  b: 0.0,    a: 1.0 }, // L (orange)
+];
+
+const MONOCHROME_COLORS: [Color; 7] = [
+    Color { r: 0.95,   g: 0.95,   b: 0.95,   a: 1.0 }, // I
+    Color { r: 0.85,   g: 0.85,   b: 0.85,   a: 1.0 }, // O
+    Color { r: 0.75,   g: 0.75,   b: 0.75,   a: 1.0 }, // T
+    Color { r: 0.65,   g: 0.65,   b: 0.65,   a: 1.0 }, // S
+    Color { r: 0.55,   g: 0.55,   b: 0.55,   a: 1.0 }, // Z
+    Color { r: 0.45,   g: 0.45,   b: 0.45,   a: 1.0 }, // J
+    Color { r: 0.35,   g: 0.35,   b: 0.35,   a: 1.0 }, // L
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum ColorScheme {
+    Classic,
+    Deuteranopia,
+    Monochrome,
+}
+
+impl ColorScheme {
+    const ALL: [ColorScheme; 3] = [ColorScheme::Classic, ColorScheme::Deuteranopia, ColorScheme::Monochrome];
+
+    fn name(self) -> &'static str {
+        match self {
+            ColorScheme::Classic => "Classic",
+            ColorScheme::Deuteranopia => "Deuteranopia",
+            ColorScheme::Monochrome => "Monochrome",
+        }
+    }
+
+    fn cycle(self, step: i32) -> ColorScheme {
+        let len = ColorScheme::ALL.len() as i32;
+        let i = ColorScheme::ALL.iter().position(|&c| c == self).unwrap() as i32;
+        ColorScheme::ALL[(i + step).rem_euclid(len) as usize]
+    }
+}
+
+// How cleared rows disappear while line_clear_timer runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum ClearAnimation {
+    #[default]
+    Flash,    // Rows blink white and black.
+    Wipe,     // Rows are erased from the center out.
+    Collapse, // Rows squeeze down to a line and vanish.
+}
+
+impl ClearAnimation {
+    const ALL: [ClearAnimation; 3] = [ClearAnimation::Flash, ClearAnimation::Wipe, ClearAnimation::Collapse];
+
+    fn name(self) -> &'static str {
+        match self {
+            ClearAnimation::Flash => "Flash",
+            ClearAnimation::Wipe => "Wipe",
+            ClearAnimation::Collapse => "Collapse",
+        }
+    }
+
+    fn cycle(self, step: i32) -> ClearAnimation {
+        let len = ClearAnimation::ALL.len() as i32;
+        let i = ClearAnimation::ALL.iter().position(|&a| a == self).unwrap() as i32;
+        ClearAnimation::ALL[(i + step).rem_euclid(len) as usize]
+    }
+}
+
+// How the ghost piece is drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum GhostStyle {
+    #[default]
+    Filled,  // Translucent blocks.
+    Outline, // Just the edge of each block.
+}
+
+impl GhostStyle {
+    const ALL: [GhostStyle; 2] = [GhostStyle::Filled, GhostStyle::Outline];
+
+    fn name(self) -> &'static str {
+        match self {
+            GhostStyle::Filled => "Filled",
+            GhostStyle::Outline => "Outline",
+        }
+    }
+
+    fn cycle(self, step: i32) -> GhostStyle {
+        let len = GhostStyle::ALL.len() as i32;
+        let i = GhostStyle::ALL.iter().position(|&g| g == self).unwrap() as i32;
+        GhostStyle::ALL[(i + step).rem_euclid(len) as usize]
+    }
+}
+
+// Which song a game mode starts on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum MusicChoice {
+    #[default]
+    InOrder,      // The first song, then on through the list.
+    Shuffle,      // A random song each game.
+    Track(usize), // Always start on this song (0-based).
+}
+
+impl MusicChoice {
+    fn name(self) -> String {
+        match self {
+            MusicChoice::InOrder => "In Order".to_string(),
+            MusicChoice::Shuffle => "Shuffle".to_string(),
+            MusicChoice::Track(i) => format!("Track {}", i + 1),
+        }
+    }
+
+    // Steps through In Order, Shuffle, then each of the `tracks` songs, wrapping at either end.
+    fn cycle(self, step: i32, tracks: usize) -> MusicChoice {
+        let len = tracks as i32 + 2;
+        let i = match self {
+            MusicChoice::InOrder => 0,
+            MusicChoice::Shuffle => 1,
+            MusicChoice::Track(t) => t.min(tracks.saturating_sub(1)) as i32 + 2,
+        };
+        match (i + step).rem_euclid(len) {
+            0 => MusicChoice::InOrder,
+            1 => MusicChoice::Shuffle,
+            t => MusicChoice::Track(t as usize - 2),
+        }
+    }
+}
+
 // MusicManager modified to use embedded audio.
 #[allow(dead_code)]
 struct MusicManager {
-    mus_stream:OutputStream,
-    mus_stream_hndl:OutputStreamHandle,
+    mus_stream:Option<(OutputStream, OutputStreamHandle)>, // Keeps the device open; None when silent.
     mus_sink:Sink,
+    sfx_sink:Sink,
     mus_track:u32,
+    tracks:Vec<Arc<[u8]>>, // Encoded songs to cycle through.
+    music_volume:f32,
     muted:bool,
+    sfx_volume:f32,
+    sfx_muted:bool, // Independent of `muted`, so effects can stay on with the music off.
     paused:bool,
+    fade_level:f32,      // Multiplier on music_volume while ramping between songs.
+    switch_pending:bool, // Fading out before starting the next song.
 }
 
 impl MusicManager {
-    fn new() -> Self {
-        let (stream, stream_handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&stream_handle).unwrap();
+    // Plays the embedded songs plus any found in CUSTOM_MUSIC_DIR, or only the
+    // custom ones when `custom_only` is set and some were found.
+    fn new(custom_only: bool) -> Self {
+        let (stream, sink, sfx_sink) = open_audio();
+        let custom = load_custom_tracks();
+        let mut tracks: Vec<Arc<[u8]>> = Vec::new();
+        if !custom_only || custom.is_empty() {
+            tracks.extend(MUSIC_LIST.iter().map(|&data| Arc::from(data)));
+        }
+        tracks.extend(custom);
         MusicManager {
             mus_stream:stream,
-            mus_stream_hndl:stream_handle,
             mus_sink:sink,
+            sfx_sink,
             mus_track:0,
+            tracks,
+            music_volume:DEFAULT_MUSIC_VOLUME,
             muted:false,
+            sfx_volume:DEFAULT_SFX_VOLUME,
+            sfx_muted:false,
             paused:false,
+            fade_level:1.0,
+            switch_pending:false,
         }
     }
 
     pub fn play_song(&mut self) {
+        self.switch_pending = false;
+        self.fade_level = 1.0;
+        self.start_track();
+    }
+
+    // Starts a game's music on the song its mode is set to.
+    pub fn play_choice(&mut self, choice: MusicChoice) {
+        self.mus_track = match choice {
+            MusicChoice::InOrder => 0,
+            MusicChoice::Shuffle => ::rand::thread_rng().gen_range(0..self.tracks.len()) as u32,
+            MusicChoice::Track(i) => i as u32,
+        };
+        self.play_song();
+    }
+
+    // Fades the current song out, then fades the next one in (see update_audio).
+    pub fn next_song(&mut self){
+        if self.mus_sink.empty() || self.paused {
+            self.play_song();
+        } else {
+            self.switch_pending = true;
+        }
+    }
+
+    // Advances a song-change fade; call once per frame.
+    pub fn update_audio(&mut self, dt: f32){
+        let step = dt / MUSIC_FADE_SECONDS;
+        if self.switch_pending {
+            self.fade_level -= step;
+            if self.fade_level <= 0.0 {
+                self.fade_level = 0.0;
+                self.switch_pending = false;
+                self.start_track();
+            }
+        } else if self.fade_level < 1.0 {
+            self.fade_level = (self.fade_level + step).min(1.0);
+        } else {
+            return;
+        }
+        self.apply_music_volume();
+    }
+
+    fn start_track(&mut self) {
         // Clear the current sink's buffer.
         self.mus_sink.clear();
-        // Determine the current track from the embedded MUSIC_LIST.
-        let track_index = (self.mus_track % MUSIC_LIST.len() as u32) as usize;
-        let track_data = MUSIC_LIST[track_index];
+        // Determine the current track from the loaded track list.
+        let track_index = (self.mus_track % self.tracks.len() as u32) as usize;
+        let track_data = self.tracks[track_index].clone();
         self.mus_track += 1;
-        // Create an in-memory cursor for the embedded audio data.
+        // Create an in-memory cursor for the audio data.
         let cursor = Cursor::new(track_data);
         // Decode the audio data and set it to repeat infinitely.
         let source = Decoder::new(cursor).unwrap().repeat_infinite();
         // Append the source into the sink and set volume.
         self.mus_sink.append(source);
-        self.mus_sink.set_volume(0.5);
+        self.apply_music_volume();
         self.mus_sink.play();
     }
 
-    pub fn mute(&mut self){
-        if self.muted{
-            self.mus_sink.set_volume(0.5);
+    // Plays a short run of sine tones over the music, after any effect still playing.
+    pub fn play_sfx(&mut self, notes: &[(f32, f32)]){
+        for &(freq, secs) in notes {
+            let tone = SineWave::new(freq).take_duration(Duration::from_secs_f32(secs)).amplify(SFX_VOLUME);
+            self.sfx_sink.append(tone);
         }
-        else{
-            self.mus_sink.set_volume(0.0);
+        self.sfx_sink.set_volume(if self.sfx_muted { 0.0 } else { self.sfx_volume });
+        self.sfx_sink.play();
+    }
+
+    pub fn set_music_volume(&mut self, volume: f32){
+        self.music_volume = volume.clamp(0.0, 1.0);
+        self.apply_music_volume();
+    }
+
+    pub fn mute_music(&mut self){
+        self.set_muted(!self.muted);
+    }
+
+    pub fn set_muted(&mut self, muted: bool){
+        self.muted = muted;
+        self.apply_music_volume();
+    }
+
+    pub fn set_sfx_volume(&mut self, volume: f32){
+        self.sfx_volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn mute_sfx(&mut self){
+        self.set_sfx_muted(!self.sfx_muted);
+    }
+
+    pub fn set_sfx_muted(&mut self, muted: bool){
+        self.sfx_muted = muted;
+        if muted {
+            self.sfx_sink.clear();
         }
-        self.muted = !self.muted;
+    }
+
+    fn apply_music_volume(&mut self){
+        let volume = if self.muted { 0.0 } else { self.music_volume * self.fade_level };
+        self.mus_sink.set_volume(volume);
     }
 
     pub fn pause(&mut self){
@@ -111,391 +447,2242 @@ impl MusicManager {
     pub fn reset(&mut self){
         self.mus_sink.clear();
         self.mus_track = 0;
+        self.switch_pending = false;
+        self.fade_level = 1.0;
     }
 }
 
-// Tetromino definitions and game structures.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-enum TetrominoType {
-    I, O, T, S, Z, J, L,
-    BonusGold, BonusSilver, // For bonus blocks.
+// The notes for a line clear: longer for more lines, and pitched up a semitone per combo step.
+fn line_clear_sfx(cleared: usize, combo: i32) -> Vec<(f32, f32)> {
+    let shift = 2f32.powf(combo.clamp(0, COMBO_SFX_MAX_STEPS) as f32 / 12.0);
+    let count = cleared.clamp(1, LINE_CLEAR_NOTES.len());
+    LINE_CLEAR_NOTES[..count]
+        .iter()
+        .enumerate()
+        .map(|(i, &freq)| {
+            // The last note rings a little longer so the clear ends on it.
+            let secs = if i + 1 == count { LINE_CLEAR_NOTE_SECONDS * 2.0 } else { LINE_CLEAR_NOTE_SECONDS };
+            (freq * shift, secs)
+        })
+        .collect()
 }
 
-const TETROMINO_SHAPES: [[[i32; 2]; 4]; 7] = [
-    [[0,0],[1,0],[2,0],[3,0]],    // I
-    [[0,0],[1,0],[0,1],[1,1]],    // O
-    [[1,0],[0,1],[1,1],[2,1]],    // T
-    [[1,0],[2,0],[0,1],[1,1]],    // S
-    [[0,0],[1,0],[1,1],[2,1]],    // Z
-    [[0,0],[0,1],[1,1],[2,1]],    // J
-    [[0,0],[1,0],[2,0],[0,1]],    // L
-];
+// Opens the default audio device with one sink for music and one for effects.
+// Without a usable device the game runs silently instead of crashing.
+#[cfg(not(any(test, feature = "headless")))]
+fn open_audio() -> (Option<(OutputStream, OutputStreamHandle)>, Sink, Sink) {
+    let opened = OutputStream::try_default().map_err(|e| e.to_string()).and_then(|(stream, stream_handle)| {
+        let sink = Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
+        let sfx_sink = Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
+        Ok((stream, stream_handle, sink, sfx_sink))
+    });
+    match opened {
+        Ok((stream, stream_handle, sink, sfx_sink)) => (Some((stream, stream_handle)), sink, sfx_sink),
+        Err(e) => {
+            eprintln!("No audio output ({}), continuing without sound", e);
+            silent_audio()
+        }
+    }
+}
 
-const TETROMINO_ROTATION_OFFSETS: [[i32; 2]; 7] = [
-    [1,0], // I
-    [0,0], // O (doesn't rotate)
-    [1,1], // T
-    [1,1], // S
-    [1,1], // Z
-    [1,1], // J
-    [1,1], // L
-];
+// Tests and headless builds (for CI and benchmarks) never touch an audio device.
+#[cfg(any(test, feature = "headless"))]
+fn open_audio() -> (Option<(OutputStream, OutputStreamHandle)>, Sink, Sink) {
+    silent_audio()
+}
 
-#[derive(Clone, Copy)]
-struct Tetromino {
-    shape: [[i32; 2]; 4],
-    pos: (i32, i32),
-    color: Color,
-    t_type: TetrominoType,
+// Idle sinks accept everything MusicManager does with them and simply never play it.
+fn silent_audio() -> (Option<(OutputStream, OutputStreamHandle)>, Sink, Sink) {
+    (None, Sink::new_idle().0, Sink::new_idle().0)
 }
 
-impl Tetromino {
-    fn new(t_type: TetrominoType) -> Self {
-        Tetromino {
-            shape: TETROMINO_SHAPES[t_type as usize],
-            pos: (GRID_WIDTH as i32 / 2 - 2, 0),
-            color: NES_COLORS[t_type as usize],
-            t_type,
+// Reads every decodable song in CUSTOM_MUSIC_DIR, in file name order. Copies of
+// the embedded songs (which live in the same folder in the source tree) are skipped.
+fn load_custom_tracks() -> Vec<Arc<[u8]>> {
+    let Ok(entries) = fs::read_dir(CUSTOM_MUSIC_DIR) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| MUSIC_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        })
+        .collect();
+    paths.sort();
+    let mut tracks = Vec::new();
+    for path in paths {
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if MUSIC_LIST.contains(&data.as_slice()) {
+            continue;
         }
+        if let Err(e) = Decoder::new(Cursor::new(data.clone())) {
+            eprintln!("Skipping {}: {}", path.display(), e);
+            continue;
+        }
+        tracks.push(Arc::from(data));
     }
+    tracks
 }
 
-fn rotate_shape(shape: &[[i32; 2]; 4], t_type: TetrominoType, clockwise: bool) -> [[i32; 2]; 4] {
-    let mut new_shape = [[0; 2]; 4];
-    let [pivot_x, pivot_y] = TETROMINO_ROTATION_OFFSETS[t_type as usize];
-    for (i, &[x, y]) in shape.iter().enumerate() {
-        let rel_x = x - pivot_x;
-        let rel_y = y - pivot_y;
-        let (nx, ny) = if clockwise {
-            (pivot_x + rel_y, pivot_y - rel_x)
-        } else {
-            (pivot_x - rel_y, pivot_y + rel_x)
+// -------------------------------------------------------------------
+// Persistent settings and records, stored next to the executable's working directory.
+const CONFIG_PATH: &str = "config.json"; // Pre-profiles settings, migrated on first run.
+const PROFILES_PATH: &str = "profiles.json";
+const MAX_PLAYER_NAME_LEN: usize = 12;
+const LEADERBOARD_PATH: &str = "leaderboard.json";
+const LEADERBOARD_SIZE: usize = 10;
+const REPLAY_DIR: &str = "replays";
+// Bumped whenever a rule change makes the same inputs play out differently (such as a new
+// soft drop, spawn position or rotation), since an older replay would no longer follow its recording.
+const REPLAY_VERSION: u32 = 3;
+const SCREENSHOT_DIR: &str = "screenshots";
+const HISTORY_PATH: &str = "history.csv";
+const DEFAULT_PLAYER_NAME: &str = "PLAYER";
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ModeRecord {
+    high_score: u32,
+    line_count: u32,
+    best_time: Option<f32>, // Fastest finish in seconds, for goal-based modes like Sprint.
+}
+
+// Keys bound to each in-game action. Stored in profiles.json by key name, e.g. "Left" or "Z".
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct KeyBindings {
+    #[serde(with = "key_name")]
+    move_left: KeyCode,
+    #[serde(with = "key_name")]
+    move_right: KeyCode,
+    #[serde(with = "key_name")]
+    soft_drop: KeyCode,
+    #[serde(with = "key_name")]
+    hard_drop: KeyCode,
+    #[serde(with = "key_name")]
+    sonic_drop: KeyCode,
+    #[serde(with = "key_name")]
+    rotate_cw: KeyCode,
+    #[serde(with = "key_name")]
+    rotate_ccw: KeyCode,
+    #[serde(with = "key_name")]
+    rotate_180: KeyCode,
+    #[serde(with = "key_name")]
+    hold: KeyCode,
+    #[serde(with = "key_name")]
+    undo: KeyCode,
+    #[serde(with = "key_name")]
+    pause: KeyCode,
+    #[serde(with = "key_name")]
+    reset_stats: KeyCode,
+}
+
+impl KeyBindings {
+    fn key(&self, action: GameAction) -> KeyCode {
+        match action {
+            GameAction::MoveLeft => self.move_left,
+            GameAction::MoveRight => self.move_right,
+            GameAction::SoftDrop => self.soft_drop,
+            GameAction::HardDrop => self.hard_drop,
+            GameAction::SonicDrop => self.sonic_drop,
+            GameAction::RotateCw => self.rotate_cw,
+            GameAction::RotateCcw => self.rotate_ccw,
+            GameAction::Rotate180 => self.rotate_180,
+            GameAction::Hold => self.hold,
+            GameAction::Undo => self.undo,
+        }
+    }
+
+    fn set(&mut self, action: GameAction, key: KeyCode) {
+        let slot = match action {
+            GameAction::MoveLeft => &mut self.move_left,
+            GameAction::MoveRight => &mut self.move_right,
+            GameAction::SoftDrop => &mut self.soft_drop,
+            GameAction::HardDrop => &mut self.hard_drop,
+            GameAction::SonicDrop => &mut self.sonic_drop,
+            GameAction::RotateCw => &mut self.rotate_cw,
+            GameAction::RotateCcw => &mut self.rotate_ccw,
+            GameAction::Rotate180 => &mut self.rotate_180,
+            GameAction::Hold => &mut self.hold,
+            GameAction::Undo => &mut self.undo,
         };
-        new_shape[i] = [nx, ny];
+        *slot = key;
     }
-    new_shape
 }
 
-struct SquareEffect {
-    x: usize,
-    y: usize,
-    is_gold: bool,
-    timer: f32,             // Duration per blink phase.
-    flash_on: bool,         // Whether bonus color is displayed.
-    blinks_remaining: u32,  // Number of on-off cycles remaining.
-    original: [[(Color, TetrominoType, u32); 4]; 4],
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            move_left: KeyCode::Left,
+            move_right: KeyCode::Right,
+            soft_drop: KeyCode::Down,
+            hard_drop: KeyCode::Up,
+            sonic_drop: KeyCode::Space,
+            rotate_cw: KeyCode::X,
+            rotate_ccw: KeyCode::Z,
+            rotate_180: KeyCode::A,
+            hold: KeyCode::C,
+            undo: KeyCode::U,
+            pause: KeyCode::Enter,
+            reset_stats: KeyCode::F1,
+        }
+    }
 }
 
-struct GameState {
-    // Each cell stores Option<(Color, TetrominoType, piece_id)>
-    board: [[Option<(Color, TetrominoType, u32)>; GRID_WIDTH]; GRID_HEIGHT],
-    tetromino: Option<Tetromino>,
-    next_tetromino: Option<Tetromino>,
-    hold_tetromino: Option<Tetromino>,
-    hold_used: bool,
+// Keys that can be named in profiles.json.
+const BINDABLE_KEYS: &[KeyCode] = &[
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+    KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::Kp0, KeyCode::Kp1, KeyCode::Kp2, KeyCode::Kp3, KeyCode::Kp4,
+    KeyCode::Kp5, KeyCode::Kp6, KeyCode::Kp7, KeyCode::Kp8, KeyCode::Kp9, KeyCode::KpEnter,
+    KeyCode::Left, KeyCode::Right, KeyCode::Up, KeyCode::Down,
+    KeyCode::Space, KeyCode::Enter, KeyCode::Escape, KeyCode::Tab, KeyCode::Backspace,
+    KeyCode::LeftShift, KeyCode::RightShift, KeyCode::LeftControl, KeyCode::RightControl,
+    KeyCode::LeftAlt, KeyCode::RightAlt,
+    KeyCode::Comma, KeyCode::Period, KeyCode::Slash, KeyCode::Semicolon, KeyCode::Apostrophe,
+    KeyCode::Minus, KeyCode::Equal, KeyCode::LeftBracket, KeyCode::RightBracket,
+    KeyCode::Backslash, KeyCode::GraveAccent,
+    KeyCode::Insert, KeyCode::Delete, KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown,
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5,
+    KeyCode::F6, KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10,
+];
 
-    started: bool,
-    paused: bool,
-    game_over: bool,
-    lines_cleared: u32,
-    score: u32,
+// Keys with fixed jobs during play (restart, mute, next song, controls text, volume, debug
+// overlay), so Settings won't bind an action to them.
+const RESERVED_KEYS: &[KeyCode] = &[
+    KeyCode::R, KeyCode::M, KeyCode::N, KeyCode::H, KeyCode::Equal, KeyCode::Minus, KeyCode::F3,
+];
 
-    left_timer: f32,
-    right_timer: f32,
-    fall_timer: f32,
+// Serializes a KeyCode by its variant name. Unknown names load as KeyCode::Unknown,
+// leaving that action unbound instead of throwing away the whole config.
+mod key_name {
+    use super::BINDABLE_KEYS;
+    use macroquad::prelude::KeyCode;
+    use serde::{Deserialize, Deserializer, Serializer};
 
-    line_clear_timer: f32,
-    clearing_lines: Vec<usize>,
+    pub fn serialize<S: Serializer>(key: &KeyCode, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", key))
+    }
 
-    active_squares: Vec<SquareEffect>,
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<KeyCode, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        match BINDABLE_KEYS.iter().find(|key| format!("{:?}", key) == name) {
+            Some(&key) => Ok(key),
+            None => {
+                eprintln!("Unknown key '{}' in config, leaving it unbound", name);
+                Ok(KeyCode::Unknown)
+            }
+        }
+    }
+}
 
-    next_piece_id: u32, // For unique locked piece tagging.
+// Horizontal auto-repeat timings, in seconds.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct Handling {
+    das: f32, // Delay before a held left/right starts repeating.
+    arr: f32, // Time between repeats; 0 slides straight to the wall.
+    soft_drop_speed: f32,    // Rows per second while soft drop is held.
+    instant_soft_drop: bool, // Soft drop goes straight to the floor (20G) without locking.
+}
 
-    mus_mgr: MusicManager,
+impl Default for Handling {
+    fn default() -> Self {
+        Handling {
+            das: DEFAULT_DAS,
+            arr: DEFAULT_ARR,
+            soft_drop_speed: DEFAULT_SOFT_DROP_SPEED,
+            instant_soft_drop: false,
+        }
+    }
+}
 
-    // Statistics counter for spawned tetrominoes.
-    piece_statistics: HashMap<TetrominoType, u32>,
+// One player's name, records, and preferences.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct Config {
+    player_name: String,
+    // Best run per game mode and board size, keyed by BoardSize::record_key().
+    records: HashMap<String, ModeRecord>,
+    key_bindings: KeyBindings,
+    handling: Handling,
+    // Starting song per game mode, keyed by GameMode::name().
+    mode_music: HashMap<String, MusicChoice>,
+    music_volume: f32,
+    muted: bool,
+    sfx_volume: f32,
+    sfx_muted: bool,
+    custom_music_only: bool, // Skip the built-in songs when resources/music has others.
+    fullscreen: bool,
+    window_width: u32, // Windowed size, restored at startup.
+    window_height: u32,
+    color_scheme: ColorScheme,
+    piece_symbols: bool, // Draw each piece's letter on its blocks.
+    grid_lines: bool,    // Faint cell grid behind the pieces.
+    hold_enabled: bool,  // Off for purists who play without hold.
+    ghost_enabled: bool, // Show where the piece will land (always off on Hard).
+    ghost_style: GhostStyle,
+    ghost_opacity: f32, // Alpha of the ghost, from 0 (invisible) to 1 (solid).
+    next_queue_size: usize, // Upcoming pieces shown under "Next", clamped to NEXT_QUEUE_SIZE_RANGE.
+    landing_info: bool, // Print the column(s) and row the current piece will land on.
+    square_hints: bool,  // Outline nearly finished 4x4 squares, to teach the bonus.
+    clear_animation: ClearAnimation,
+    confirm_hard_drop: bool, // Hard drop needs a double tap, so a stray press can't drop a piece.
+    mirrored: bool,          // Challenge modifier: the board is drawn flipped left to right.
+    // Timed and Ultra countdowns stop during the line-clear freeze. Off by default, so the
+    // clock keeps running through clears as it always has.
+    clock_pauses_on_clear: bool,
+    history_csv: bool, // Append a summary of every finished game to history.csv.
+    // Controls text under the board. None until the player chooses: shown through the
+    // first game, then hidden.
+    show_controls: Option<bool>,
+    invisible_delay: f32, // Seconds locked blocks stay visible in Invisible mode.
+    danger_effect: bool,  // Pulse a red vignette around the board when the stack is high.
+    danger_height: usize, // Stack height, in standard rows, where the danger effect starts.
 }
 
-impl GameState {
-    pub fn new() -> Self {
-        let mut piece_statistics = HashMap::new();
-        // Initialize counter for the main tetromino types.
-        for &piece in &[
-            TetrominoType::I,
-            TetrominoType::O,
-            TetrominoType::T,
-            TetrominoType::S,
-            TetrominoType::Z,
-            TetrominoType::J,
-            TetrominoType::L,
-        ] {
-            piece_statistics.insert(piece, 0);
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            player_name: DEFAULT_PLAYER_NAME.to_string(),
+            records: HashMap::new(),
+            key_bindings: KeyBindings::default(),
+            handling: Handling::default(),
+            mode_music: HashMap::new(),
+            music_volume: DEFAULT_MUSIC_VOLUME,
+            muted: false,
+            sfx_volume: DEFAULT_SFX_VOLUME,
+            sfx_muted: false,
+            custom_music_only: false,
+            fullscreen: false,
+            window_width: WINDOW_PRESETS[0].0,
+            window_height: WINDOW_PRESETS[0].1,
+            color_scheme: ColorScheme::Classic,
+            piece_symbols: false,
+            grid_lines: true,
+            hold_enabled: true,
+            ghost_enabled: true,
+            ghost_style: GhostStyle::Filled,
+            ghost_opacity: DEFAULT_GHOST_OPACITY,
+            next_queue_size: DEFAULT_NEXT_QUEUE_SIZE,
+            landing_info: false,
+            square_hints: false,
+            clear_animation: ClearAnimation::Flash,
+            confirm_hard_drop: false,
+            mirrored: false,
+            clock_pauses_on_clear: false,
+            history_csv: false,
+            show_controls: None,
+            invisible_delay: DEFAULT_INVISIBLE_DELAY,
+            danger_effect: true,
+            danger_height: DEFAULT_DANGER_HEIGHT,
         }
+    }
+}
 
-        Self {
-            board: [[None; GRID_WIDTH]; GRID_HEIGHT],
-            tetromino: None,
-            next_tetromino: None,
-            hold_tetromino: None,
-            hold_used: false,
-            started: false,
-            paused: false,
-            game_over: false,
-            lines_cleared: 0,
-            score: 0,
-            left_timer: 0.0,
-            right_timer: 0.0,
-            fall_timer: 0.0,
-            line_clear_timer: 0.0,
-            clearing_lines: Vec::new(),
-            active_squares: Vec::new(),
-            next_piece_id: 1,
-            mus_mgr: MusicManager::new(),
-            piece_statistics,
+// One finished run on the leaderboard.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ScoreEntry {
+    name: String,
+    score: u32,
+    lines: u32,
+    mode: String,
+    timestamp: u64, // Seconds since the Unix epoch.
+}
+
+// In-game actions a replay can record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum GameAction {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    SonicDrop, // Drops to the floor like a hard drop but leaves the piece free to slide.
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    Hold,
+    Undo, // Takes back the last lock in Practice mode.
+}
+
+impl GameAction {
+    const ALL: [GameAction; 10] = [
+        GameAction::MoveLeft,
+        GameAction::MoveRight,
+        GameAction::SoftDrop,
+        GameAction::HardDrop,
+        GameAction::SonicDrop,
+        GameAction::RotateCw,
+        GameAction::RotateCcw,
+        GameAction::Rotate180,
+        GameAction::Hold,
+        GameAction::Undo,
+    ];
+
+    // Label for the key rows in Settings.
+    fn name(self) -> &'static str {
+        match self {
+            GameAction::MoveLeft => "Move Left",
+            GameAction::MoveRight => "Move Right",
+            GameAction::SoftDrop => "Soft Drop",
+            GameAction::HardDrop => "Hard Drop",
+            GameAction::SonicDrop => "Sonic Drop",
+            GameAction::RotateCw => "Rotate CW",
+            GameAction::RotateCcw => "Rotate CCW",
+            GameAction::Rotate180 => "Rotate 180",
+            GameAction::Hold => "Hold",
+            GameAction::Undo => "Undo",
         }
     }
+}
 
-    pub fn start_game(&mut self) {
-        self.started = true;
-        self.game_over = false;
-        self.paused = false;
-        self.lines_cleared = 0;
-        self.score = 0;
-        self.board = [[None; GRID_WIDTH]; GRID_HEIGHT];
-        self.hold_tetromino = None;
-        self.hold_used = false;
-        self.line_clear_timer = 0.0;
-        self.clearing_lines.clear();
-        self.active_squares.clear();
-        self.next_piece_id = 1;
+// A key going down or coming back up. Held actions (DAS, soft drop) stay
+// active from their Press until the matching Release.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum InputAction {
+    Press(GameAction),
+    Release(GameAction),
+}
 
-        // Reset statistics at the start of a new game.
-        self.piece_statistics.clear();
-        for &piece in &[
-            TetrominoType::I,
-            TetrominoType::O,
-            TetrominoType::T,
-            TetrominoType::S,
-            TetrominoType::Z,
-            TetrominoType::J,
-            TetrominoType::L,
-        ] {
-            self.piece_statistics.insert(piece, 0);
-        }
+// A recorded run: the settings it started from plus every input, keyed by frame.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Replay {
+    version: u32, // REPLAY_VERSION when recorded; replays from before versioning load as 0.
+    mode: GameMode,
+    difficulty: Difficulty,
+    board_size: BoardSize,
+    start_level: u32,
+    #[serde(default = "default_hold_enabled")]
+    hold_enabled: bool,
+    handling: Handling, // Older replays load with the default timings they were played with.
+    confirm_hard_drop: bool,
+    mirrored: bool,
+    clock_pauses_on_clear: bool,
+    seed: u64,
+    inputs: Vec<(u32, InputAction)>,
+}
 
-        let mut rng = thread_rng();
-        let curr_type = match rng.gen_range(0..7) {
-            0 => TetrominoType::I,
-            1 => TetrominoType::O,
-            2 => TetrominoType::T,
-            3 => TetrominoType::S,
-            4 => TetrominoType::Z,
-            5 => TetrominoType::J,
-            _ => TetrominoType::L,
-        };
-        let next_type = match rng.gen_range(0..7) {
-            0 => TetrominoType::I,
-            1 => TetrominoType::O,
-            2 => TetrominoType::T,
-            3 => TetrominoType::S,
-            4 => TetrominoType::Z,
-            5 => TetrominoType::J,
-            _ => TetrominoType::L,
-        };
+// Replays from before hold could be switched off were all played with it on.
+fn default_hold_enabled() -> bool {
+    true
+}
 
-        self.tetromino = Some(Tetromino::new(curr_type));
-        // Count the active tetromino spawn.
-        *self.piece_statistics.entry(curr_type).or_insert(0) += 1;
+// Action state for one simulated frame, indexed by GameAction.
+#[derive(Clone, Copy, Debug, Default)]
+struct InputFrame {
+    pressed: [bool; GameAction::ALL.len()],  // Went down this frame.
+    released: [bool; GameAction::ALL.len()], // Came back up this frame.
+    down: [bool; GameAction::ALL.len()],     // Currently held.
+}
 
-        self.next_tetromino = Some(Tetromino::new(next_type));
-        self.mus_mgr.play_song();
+impl InputFrame {
+    fn pressed(&self, action: GameAction) -> bool {
+        self.pressed[action as usize]
     }
 
-    pub fn check_collision(&self, shape: &[[i32; 2]; 4], pos: (i32, i32)) -> bool {
-        for &[dx, dy] in shape {
-            let x = pos.0 + dx;
-            let y = pos.1 + dy;
-            if x < 0 || x >= GRID_WIDTH as i32 || y < 0 || y >= GRID_HEIGHT as i32 {
-                return true;
-            }
-            if self.board[y as usize][x as usize].is_some() {
-                return true;
-            }
+    fn released(&self, action: GameAction) -> bool {
+        self.released[action as usize]
+    }
+
+    fn down(&self, action: GameAction) -> bool {
+        self.down[action as usize]
+    }
+
+    // The same frame with left and right swapped, for playing on a mirrored board.
+    fn mirrored(&self) -> InputFrame {
+        let mut frame = *self;
+        let (left, right) = (GameAction::MoveLeft as usize, GameAction::MoveRight as usize);
+        for state in [&mut frame.pressed, &mut frame.released, &mut frame.down] {
+            state.swap(left, right);
         }
-        false
+        frame
     }
+}
 
-    pub fn lock_tetromino(&mut self) {
+// Where the simulation reads its actions from each frame.
+trait InputSource {
+    fn poll(&mut self, frame: u32) -> InputFrame;
+}
+
+// Live keyboard input. Presses and releases are gathered every rendered frame
+// and held until the next simulated frame consumes them, so none are lost when
+// the display runs faster or slower than the simulation.
+#[derive(Default)]
+struct KeyboardInput {
+    pending: InputFrame,
+}
+
+impl KeyboardInput {
+    fn gather(&mut self, keys: &KeyBindings) {
+        for action in GameAction::ALL {
+            let key = keys.key(action);
+            let i = action as usize;
+            self.pending.pressed[i] |= is_key_pressed(key);
+            self.pending.released[i] |= is_key_released(key);
+            self.pending.down[i] = is_key_down(key);
+        }
+    }
+
+    fn clear_presses(&mut self) {
+        self.pending.pressed = Default::default();
+    }
+}
+
+impl InputSource for KeyboardInput {
+    fn poll(&mut self, _frame: u32) -> InputFrame {
+        let input = self.pending;
+        self.pending.pressed = Default::default();
+        self.pending.released = Default::default();
+        input
+    }
+}
+
+// Feeds a saved replay's inputs back in on the frames they were recorded.
+struct ReplayInput {
+    replay: Replay,
+    next: usize, // Index of the first input not yet played.
+    held: [bool; GameAction::ALL.len()],
+}
+
+impl ReplayInput {
+    fn new(replay: Replay) -> Self {
+        ReplayInput {
+            replay,
+            next: 0,
+            held: Default::default(),
+        }
+    }
+
+    // Goes back to the first recorded input.
+    fn rewind(&mut self) {
+        self.next = 0;
+        self.held = Default::default();
+    }
+
+    fn load(path: &str) -> Option<Self> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("Failed to read replay {}: {}", path, e);
+                return None;
+            }
+        };
+        match serde_json::from_str::<Replay>(&json) {
+            Ok(replay) if replay.version != REPLAY_VERSION => {
+                eprintln!(
+                    "Replay {} was recorded with older game rules (version {}, this build plays version {})",
+                    path, replay.version, REPLAY_VERSION
+                );
+                None
+            }
+            Ok(replay) => Some(ReplayInput::new(replay)),
+            Err(e) => {
+                eprintln!("Failed to parse replay {}: {}", path, e);
+                None
+            }
+        }
+    }
+}
+
+impl InputSource for ReplayInput {
+    fn poll(&mut self, frame: u32) -> InputFrame {
+        let mut input = InputFrame {
+            down: self.held,
+            ..Default::default()
+        };
+        while let Some(&(at, event)) = self.replay.inputs.get(self.next) {
+            if at > frame {
+                break;
+            }
+            self.next += 1;
+            match event {
+                InputAction::Press(action) => {
+                    input.pressed[action as usize] = true;
+                    input.down[action as usize] = true;
+                }
+                InputAction::Release(action) => {
+                    input.released[action as usize] = true;
+                    input.down[action as usize] = false;
+                }
+            }
+        }
+        self.held = input.down;
+        input
+    }
+}
+
+// Most recently written file in REPLAY_DIR.
+fn latest_replay_path() -> Option<String> {
+    fs::read_dir(REPLAY_DIR)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "replay"))
+        .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .map(|entry| entry.path().to_string_lossy().into_owned())
+}
+
+impl Config {
+    fn record(&self, mode: GameMode, board_size: BoardSize) -> ModeRecord {
+        self.records.get(&board_size.record_key(mode)).copied().unwrap_or_default()
+    }
+
+    fn music_choice(&self, mode: GameMode) -> MusicChoice {
+        self.mode_music.get(mode.name()).copied().unwrap_or_default()
+    }
+}
+
+// Reads a JSON file, falling back to the default value if it's missing or unreadable.
+// A file that doesn't parse is moved aside to `<path>.corrupt` rather than being
+// overwritten by the next save, so its contents can still be recovered by hand.
+fn load_json<T: DeserializeOwned + Default>(path: &str) -> T {
+    let json = match fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return T::default(),
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path, e);
+            return T::default();
+        }
+    };
+    match serde_json::from_str(&json) {
+        Ok(value) => value,
+        Err(e) => {
+            let corrupt_path = format!("{}.corrupt", path);
+            eprintln!("Failed to parse {}: {}; moving it to {}", path, e, corrupt_path);
+            if let Err(e) = fs::rename(path, &corrupt_path) {
+                eprintln!("Failed to move {} aside: {}", path, e);
+            }
+            T::default()
+        }
+    }
+}
+
+// Writes to `<path>.tmp` and renames it into place so a crash mid-write never
+// leaves a truncated file. The previous version is kept as `<path>.bak`.
+fn save_json<T: Serialize>(path: &str, value: &T) {
+    let json = match serde_json::to_string_pretty(value) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("Failed to serialize {}: {}", path, e);
+            return;
+        }
+    };
+    let tmp_path = format!("{}.tmp", path);
+    if let Err(e) = fs::write(&tmp_path, json) {
+        eprintln!("Failed to save {}: {}", path, e);
+        return;
+    }
+    if Path::new(path).exists() {
+        if let Err(e) = fs::copy(path, format!("{}.bak", path)) {
+            eprintln!("Failed to back up {}: {}", path, e);
+        }
+    }
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        eprintln!("Failed to save {}: {}", path, e);
+    }
+}
+
+// Every player profile and which one is in use.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Profiles {
+    active: usize,
+    list: Vec<Config>,
+}
+
+// A player name fit for the HUD and leaderboard: letters, digits, and single spaces between
+// words, at most MAX_PLAYER_NAME_LEN long. Falls back to the default name when nothing is left.
+fn tidy_player_name(name: &str) -> String {
+    let kept: String = name.chars().filter(|c| c.is_ascii_alphanumeric() || *c == ' ').collect();
+    let joined = kept.split_whitespace().collect::<Vec<_>>().join(" ");
+    let tidy = joined.chars().take(MAX_PLAYER_NAME_LEN).collect::<String>().trim_end().to_ascii_uppercase();
+    if tidy.is_empty() { DEFAULT_PLAYER_NAME.to_string() } else { tidy }
+}
+
+// Loads profiles.json, or on first run turns an existing config.json into the first profile.
+fn load_profiles() -> Profiles {
+    let mut profiles = if Path::new(PROFILES_PATH).exists() {
+        load_json(PROFILES_PATH)
+    } else {
+        Profiles {
+            active: 0,
+            list: vec![load_json(CONFIG_PATH)],
+        }
+    };
+    if profiles.list.is_empty() {
+        profiles.list.push(Config::default());
+    }
+    profiles.active = profiles.active.min(profiles.list.len() - 1);
+    profiles
+}
+
+// Game modes selectable on the start screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum GameMode {
+    #[default]
+    Classic,
+    Endless,
+    Timed,
+    Sprint,
+    Ultra,
+    Dig,
+    Invisible,
+    Marathon,
+    Survival,
+    Big,
+    Practice,
+    Zen,
+}
+
+impl GameMode {
+    const ALL: [GameMode; 12] = [
+        GameMode::Classic,
+        GameMode::Endless,
+        GameMode::Timed,
+        GameMode::Sprint,
+        GameMode::Ultra,
+        GameMode::Dig,
+        GameMode::Invisible,
+        GameMode::Marathon,
+        GameMode::Survival,
+        GameMode::Big,
+        GameMode::Practice,
+        GameMode::Zen,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            GameMode::Classic => "Classic",
+            GameMode::Endless => "Endless",
+            GameMode::Timed => "Timed",
+            GameMode::Sprint => "Sprint",
+            GameMode::Ultra => "Ultra",
+            GameMode::Dig => "Dig",
+            GameMode::Invisible => "Invisible",
+            GameMode::Marathon => "Marathon",
+            GameMode::Survival => "Survival",
+            GameMode::Big => "Big",
+            GameMode::Practice => "Practice",
+            GameMode::Zen => "Zen",
+        }
+    }
+
+    // Modes that run a clock up to a goal and keep the best finishing time.
+    fn is_race(self) -> bool {
+        matches!(self, GameMode::Sprint | GameMode::Dig)
+    }
+
+    // Modes that can be won, timed on race_time. Marathon is still ranked by score.
+    fn has_goal(self) -> bool {
+        self.is_race() || self == GameMode::Marathon
+    }
+
+    // Board cells per logical cell on each axis. Big plays on a half-size grid drawn
+    // at double tile size, so every piece cell covers a 2x2 area of the normal board.
+    fn cell_scale(self) -> usize {
+        if self == GameMode::Big { 2 } else { 1 }
+    }
+
+    // Zen is for warming up, so it never sets a record or high score.
+    fn keeps_scores(self) -> bool {
+        self != GameMode::Zen
+    }
+
+    // Modes that end when time_remaining runs out.
+    fn has_countdown(self) -> bool {
+        matches!(self, GameMode::Timed | GameMode::Ultra)
+    }
+
+    // Steps through ALL, wrapping at either end.
+    fn cycle(self, step: i32) -> GameMode {
+        let len = GameMode::ALL.len() as i32;
+        let i = GameMode::ALL.iter().position(|&m| m == self).unwrap() as i32;
+        GameMode::ALL[(i + step).rem_euclid(len) as usize]
+    }
+}
+
+// Difficulty picked on the start screen. It sets the starting level, stretches
+// or keeps the gravity table, and shortens or lengthens the lock delay:
+//   Easy:   level 1, gravity x1.5 (0.50 s per row at level 1), 0.75 s lock delay
+//   Normal: level 1, gravity x1.0 (0.33 s per row at level 1), 0.50 s lock delay
+//   Hard:   level 5, gravity x1.0 (0.17 s per row at level 5), 0.30 s lock delay
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    fn start_level(self) -> u32 {
+        match self {
+            Difficulty::Easy | Difficulty::Normal => 1,
+            Difficulty::Hard => 5,
+        }
+    }
+
+    // Multiplier on the seconds-per-row from gravity_interval.
+    fn gravity_scale(self) -> f32 {
+        match self {
+            Difficulty::Easy => 1.5,
+            Difficulty::Normal | Difficulty::Hard => 1.0,
+        }
+    }
+
+    // Multiplier on the 4x4 square bonus, on top of the level.
+    fn square_bonus_scale(self) -> u32 {
+        match self {
+            Difficulty::Easy | Difficulty::Normal => 1,
+            Difficulty::Hard => 2,
+        }
+    }
+
+    fn lock_delay(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => LOCK_DELAY,
+            Difficulty::Hard => 0.3,
+        }
+    }
+
+    // Steps through ALL, wrapping at either end.
+    fn cycle(self, step: i32) -> Difficulty {
+        let len = Difficulty::ALL.len() as i32;
+        let i = Difficulty::ALL.iter().position(|&d| d == self).unwrap() as i32;
+        Difficulty::ALL[(i + step).rem_euclid(len) as usize]
+    }
+}
+
+// Playfield sizes selectable on the start screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum BoardSize {
+    #[default]
+    Standard,
+    Mini,
+    Wide,
+    Short,
+}
+
+impl BoardSize {
+    const ALL: [BoardSize; 4] = [BoardSize::Standard, BoardSize::Mini, BoardSize::Wide, BoardSize::Short];
+
+    fn name(self) -> &'static str {
+        match self {
+            BoardSize::Standard => "Standard 10x20",
+            BoardSize::Mini => "Mini 6x20",
+            BoardSize::Wide => "Wide 12x20",
+            BoardSize::Short => "Short 10x16",
+        }
+    }
+
+    // (width, height) in cells.
+    fn dims(self) -> (usize, usize) {
+        match self {
+            BoardSize::Standard => (GRID_WIDTH, GRID_HEIGHT),
+            BoardSize::Mini => (6, GRID_HEIGHT),
+            BoardSize::Wide => (12, GRID_HEIGHT),
+            BoardSize::Short => (GRID_WIDTH, 16),
+        }
+    }
+
+    // The mode as records and the leaderboard file it: plain for the standard board,
+    // with the size after it otherwise (e.g. "Sprint 6x20"), so sizes never share a best.
+    fn record_key(self, mode: GameMode) -> String {
+        match self {
+            BoardSize::Standard => mode.name().to_string(),
+            _ => {
+                let (width, height) = self.dims();
+                format!("{} {}x{}", mode.name(), width, height)
+            }
+        }
+    }
+
+    // Steps through ALL, wrapping at either end.
+    fn cycle(self, step: i32) -> BoardSize {
+        let len = BoardSize::ALL.len() as i32;
+        let i = BoardSize::ALL.iter().position(|&b| b == self).unwrap() as i32;
+        BoardSize::ALL[(i + step).rem_euclid(len) as usize]
+    }
+}
+
+// Rows of the start-screen options lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MenuOption {
+    Player,
+    Mode,
+    Music,
+    Difficulty,
+    Board,
+    Settings, // Opens the Settings list.
+    Volume,
+    SfxVolume, // Enter mutes or unmutes the effects.
+    Key(GameAction), // Enter waits for a new key for this action.
+    Back,            // Returns from Settings to the main list.
+    Colors,
+    Symbols,
+    Grid,
+    Hold,
+    Ghost,
+    GhostStyle,
+    Landing,
+    Hints,
+    ClearEffect,
+    ClearClock,
+    HardDrop,
+    Mirror,
+    Window,
+    Seed,
+}
+
+// What to play: the rows that shape the next game.
+const MENU_OPTIONS: [MenuOption; 9] = [
+    MenuOption::Player,
+    MenuOption::Mode,
+    MenuOption::Music,
+    MenuOption::Difficulty,
+    MenuOption::Board,
+    MenuOption::Hold,
+    MenuOption::Mirror,
+    MenuOption::Seed,
+    MenuOption::Settings,
+];
+
+// How it looks, sounds, and handles, saved with the profile.
+const SETTINGS_OPTIONS: [MenuOption; 24] = [
+    MenuOption::Volume,
+    MenuOption::SfxVolume,
+    MenuOption::Colors,
+    MenuOption::Symbols,
+    MenuOption::Grid,
+    MenuOption::Ghost,
+    MenuOption::GhostStyle,
+    MenuOption::Landing,
+    MenuOption::Hints,
+    MenuOption::ClearEffect,
+    MenuOption::ClearClock,
+    MenuOption::HardDrop,
+    MenuOption::Window,
+    MenuOption::Key(GameAction::MoveLeft),
+    MenuOption::Key(GameAction::MoveRight),
+    MenuOption::Key(GameAction::SoftDrop),
+    MenuOption::Key(GameAction::HardDrop),
+    MenuOption::Key(GameAction::SonicDrop),
+    MenuOption::Key(GameAction::RotateCw),
+    MenuOption::Key(GameAction::RotateCcw),
+    MenuOption::Key(GameAction::Rotate180),
+    MenuOption::Key(GameAction::Hold),
+    MenuOption::Key(GameAction::Undo),
+    MenuOption::Back,
+];
+
+// Which options list the start screen shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MenuScreen {
+    Main,
+    Settings,
+}
+
+impl MenuScreen {
+    fn options(self) -> &'static [MenuOption] {
+        match self {
+            MenuScreen::Main => &MENU_OPTIONS,
+            MenuScreen::Settings => &SETTINGS_OPTIONS,
+        }
+    }
+}
+
+// Why the last run ended, which decides how its ending is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum EndReason {
+    #[default]
+    TopOut,      // The next piece had nowhere to spawn.
+    TimeUp,      // A countdown mode ran out of time.
+    GoalReached, // The mode's goal was met and the run is won.
+    Quit,        // The window was closed mid-run.
+}
+
+// Choices on the pause menu.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PauseOption {
+    Resume,
+    Restart,
+    Quit,
+}
+
+impl PauseOption {
+    const ALL: [PauseOption; 3] = [PauseOption::Resume, PauseOption::Restart, PauseOption::Quit];
+
+    fn name(self) -> &'static str {
+        match self {
+            PauseOption::Resume => "Resume",
+            PauseOption::Restart => "Restart",
+            PauseOption::Quit => "Quit to Main Menu",
+        }
+    }
+}
+
+// Tetromino definitions and game structures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum TetrominoType {
+    I, O, T, S, Z, J, L,
+    Garbage, // Pre-filled rows in Dig mode and rising rows in Survival.
+}
+
+const TETROMINO_SHAPES: [[[i32; 2]; 4]; 7] = [
+    [[0,0],[1,0],[2,0],[3,0]],    // I
+    [[0,0],[1,0],[0,1],[1,1]],    // O
+    [[1,0],[0,1],[1,1],[2,1]],    // T
+    [[1,0],[2,0],[0,1],[1,1]],    // S
+    [[0,0],[1,0],[1,1],[2,1]],    // Z
+    [[0,0],[0,1],[1,1],[2,1]],    // J
+    [[2,0],[0,1],[1,1],[2,1]],    // L
+];
+
+// Whole-cell pivots that rotate_shape turns each piece about.
+const TETROMINO_ROTATION_OFFSETS: [[i32; 2]; 7] = [
+    [1,0], // I (unused: it turns by I_SHAPES)
+    [0,0], // O (unused: it never turns)
+    [1,1], // T
+    [1,1], // S
+    [1,1], // Z
+    [1,1], // J
+    [1,1], // L
+];
+
+// SRS wall-kick offsets, indexed by the rotation state being left (0, R, 2, L).
+// Converted to board coordinates, so positive y points down.
+const JLSTZ_KICKS_CW: [[[i32; 2]; 5]; 4] = [
+    [[0,0],[-1,0],[-1,-1],[0,2],[-1,2]],  // 0 -> R
+    [[0,0],[1,0],[1,1],[0,-2],[1,-2]],    // R -> 2
+    [[0,0],[1,0],[1,-1],[0,2],[1,2]],     // 2 -> L
+    [[0,0],[-1,0],[-1,1],[0,-2],[-1,-2]], // L -> 0
+];
+
+const JLSTZ_KICKS_CCW: [[[i32; 2]; 5]; 4] = [
+    [[0,0],[1,0],[1,-1],[0,2],[1,2]],     // 0 -> L
+    [[0,0],[1,0],[1,1],[0,-2],[1,-2]],    // R -> 0
+    [[0,0],[-1,0],[-1,-1],[0,2],[-1,2]],  // 2 -> R
+    [[0,0],[-1,0],[-1,1],[0,-2],[-1,-2]], // L -> 2
+];
+
+const I_KICKS_CW: [[[i32; 2]; 5]; 4] = [
+    [[0,0],[-2,0],[1,0],[-2,1],[1,-2]],   // 0 -> R
+    [[0,0],[-1,0],[2,0],[-1,-2],[2,1]],   // R -> 2
+    [[0,0],[2,0],[-1,0],[2,-1],[-1,2]],   // 2 -> L
+    [[0,0],[1,0],[-2,0],[1,2],[-2,-1]],   // L -> 0
+];
+
+const I_KICKS_CCW: [[[i32; 2]; 5]; 4] = [
+    [[0,0],[-1,0],[2,0],[-1,-2],[2,1]],   // 0 -> L
+    [[0,0],[2,0],[-1,0],[2,-1],[-1,2]],   // R -> 0
+    [[0,0],[1,0],[-2,0],[1,2],[-2,-1]],   // 2 -> R
+    [[0,0],[-2,0],[1,0],[-2,1],[1,-2]],   // L -> 2
+];
+
+const O_KICKS: [[i32; 2]; 5] = [[0,0]; 5];
+
+// SRS turns the I about the corner at (1.5, 0.5), which no whole-cell pivot can express,
+// so its four states (0, R, 2, L) are listed outright, with state 0 matching its spawn shape.
+const I_SHAPES: [[[i32; 2]; 4]; 4] = [
+    [[0,0],[1,0],[2,0],[3,0]],    // 0
+    [[2,-1],[2,0],[2,1],[2,2]],   // R
+    [[0,1],[1,1],[2,1],[3,1]],    // 2
+    [[1,-1],[1,0],[1,1],[1,2]],   // L
+];
+
+// 180-degree turns have no SRS table; try in place, then up, then one column either way.
+const KICKS_180: [[i32; 2]; 4] = [[0,0],[0,-1],[1,0],[-1,0]];
+
+#[derive(Clone, Copy)]
+struct Tetromino {
+    shape: [[i32; 2]; 4],
+    pos: (i32, i32),
+    color: Color,
+    t_type: TetrominoType,
+    rotation: usize, // 0 = spawn, 1 = R, 2 = 180, 3 = L
+}
+
+// Where a piece in spawn orientation enters a board `width` cells wide: centered, rounding
+// left, so on 10 columns I and the three-wide pieces take columns 3-6 and 3-5, and O 4-5.
+fn spawn_pos(t_type: TetrominoType, width: usize) -> (i32, i32) {
+    let piece_width = TETROMINO_SHAPES[t_type as usize].iter().map(|&[x, _]| x).max().unwrap_or(0) + 1;
+    ((width as i32 - piece_width) / 2, 0)
+}
+
+impl Tetromino {
+    // A piece in spawn orientation at the top middle of a board `width` cells wide.
+    fn new(t_type: TetrominoType, scheme: ColorScheme, width: usize) -> Self {
+        Tetromino {
+            shape: TETROMINO_SHAPES[t_type as usize],
+            pos: spawn_pos(t_type, width),
+            color: piece_color(t_type, scheme),
+            t_type,
+            rotation: 0,
+        }
+    }
+}
+
+fn rotate_shape(shape: &[[i32; 2]; 4], t_type: TetrominoType, clockwise: bool) -> [[i32; 2]; 4] {
+    let mut new_shape = [[0; 2]; 4];
+    let [pivot_x, pivot_y] = TETROMINO_ROTATION_OFFSETS[t_type as usize];
+    for (i, &[x, y]) in shape.iter().enumerate() {
+        let rel_x = x - pivot_x;
+        let rel_y = y - pivot_y;
+        // Board y grows downward, so a clockwise turn maps (x, y) to (-y, x).
+        let (nx, ny) = if clockwise {
+            (pivot_x - rel_y, pivot_y + rel_x)
+        } else {
+            (pivot_x + rel_y, pivot_y - rel_x)
+        };
+        new_shape[i] = [nx, ny];
+    }
+    new_shape
+}
+
+fn piece_color(t_type: TetrominoType, scheme: ColorScheme) -> Color {
+    match t_type {
+        TetrominoType::Garbage => GARBAGE_COLOR,
+        _ => match scheme {
+            ColorScheme::Classic => NES_COLORS[t_type as usize],
+            ColorScheme::Deuteranopia => DEUTERANOPIA_COLORS[t_type as usize],
+            ColorScheme::Monochrome => MONOCHROME_COLORS[t_type as usize],
+        },
+    }
+}
+
+fn piece_symbol(t_type: TetrominoType) -> &'static str {
+    match t_type {
+        TetrominoType::I => "I",
+        TetrominoType::O => "O",
+        TetrominoType::T => "T",
+        TetrominoType::S => "S",
+        TetrominoType::Z => "Z",
+        TetrominoType::J => "J",
+        TetrominoType::L => "L",
+        TetrominoType::Garbage => "",
+    }
+}
+
+// Seconds between gravity steps at the given level.
+fn gravity_interval(level: u32) -> f32 {
+    let frames = GRAVITY_FRAMES.get(level.saturating_sub(1) as usize).copied().unwrap_or(1);
+    frames as f32 / 60.0
+}
+
+// The piece after a quarter turn, using the first kick that fits, or None if none do.
+// The O turns in place without moving, so it always fits.
+fn rotated(board: &[Vec<Cell>], t: &Tetromino, clockwise: bool) -> Option<Tetromino> {
+    if t.t_type == TetrominoType::O {
+        return Some(*t);
+    }
+    let rotation = if clockwise { (t.rotation + 1) % 4 } else { (t.rotation + 3) % 4 };
+    let shape = match t.t_type {
+        TetrominoType::I => I_SHAPES[rotation],
+        _ => rotate_shape(&t.shape, t.t_type, clockwise),
+    };
+    wall_kicks(t.t_type, t.rotation, clockwise)
+        .iter()
+        .map(|&[kx, ky]| (t.pos.0 + kx, t.pos.1 + ky))
+        .find(|&pos| !collides(board, &shape, pos))
+        .map(|pos| Tetromino { shape, pos, rotation, ..*t })
+}
+
+// The piece flipped in one step, using the small KICKS_180 set, or None if it can't turn.
+fn rotated_180(board: &[Vec<Cell>], t: &Tetromino) -> Option<Tetromino> {
+    if t.t_type == TetrominoType::O {
+        return Some(*t);
+    }
+    let shape = match t.t_type {
+        TetrominoType::I => I_SHAPES[(t.rotation + 2) % 4],
+        _ => rotate_shape(&rotate_shape(&t.shape, t.t_type, true), t.t_type, true),
+    };
+    KICKS_180
+        .iter()
+        .map(|&[kx, ky]| (t.pos.0 + kx, t.pos.1 + ky))
+        .find(|&pos| !collides(board, &shape, pos))
+        .map(|pos| Tetromino { shape, pos, rotation: (t.rotation + 2) % 4, ..*t })
+}
+
+// Returns the kick offsets to try, in order, when rotating out of `rotation`.
+fn wall_kicks(t_type: TetrominoType, rotation: usize, clockwise: bool) -> &'static [[i32; 2]; 5] {
+    match (t_type, clockwise) {
+        (TetrominoType::O, _) => &O_KICKS,
+        (TetrominoType::I, true) => &I_KICKS_CW[rotation],
+        (TetrominoType::I, false) => &I_KICKS_CCW[rotation],
+        (_, true) => &JLSTZ_KICKS_CW[rotation],
+        (_, false) => &JLSTZ_KICKS_CCW[rotation],
+    }
+}
+
+// Fading streak and white flash left behind by a hard drop.
+struct LockFlash {
+    cells: [[i32; 2]; 4], // Board cells the piece locked into.
+    distance: i32,        // Rows the piece fell.
+    timer: f32,
+}
+
+// A fragment of a cleared block, in board cell coordinates.
+struct Particle {
+    x: f32,
+    y: f32,
+    vx: f32, // Cells per second.
+    vy: f32,
+    color: Color,
+    life: f32, // Seconds left before it disappears.
+}
+
+struct SquareEffect {
+    x: usize,
+    y: usize,
+    is_gold: bool,
+    points: u32,            // Bonus awarded, for the popup.
+    timer: f32,             // Duration per blink phase.
+    flash_on: bool,         // Whether bonus color is displayed.
+    blinks_remaining: u32,  // Number of on-off cycles remaining.
+    age: f32,               // Seconds since the square formed.
+    original: [[(Color, TetrominoType, u32); 4]; 4],
+}
+
+// Practice mode's record of the game just before a piece locked, restored by undo.
+struct PracticeSnapshot {
+    board: Vec<Vec<Cell>>,
+    piece: TetrominoType, // The piece that locked; undo deals it again from the top.
+    next_queue: VecDeque<Tetromino>,
+    hold_tetromino: Option<Tetromino>,
+    hold_used: bool,
+    piece_bag: Vec<TetrominoType>,
+    rng: StdRng,
+    score: u32,
+    lines_cleared: u32,
+    level: u32,
+    combo: i32,
+    back_to_back: bool,
+    piece_statistics: HashMap<TetrominoType, u32>,
+    pieces_since: HashMap<TetrominoType, u32>,
+    squared_pieces: HashSet<u32>,
+    finesse_errors: u32,
+}
+
+// Each cell stores Option<(Color, TetrominoType, piece_id)>
+type Cell = Option<(Color, TetrominoType, u32)>;
+
+// -------------------------------------------------------------------
+// Board logic. Plain functions over board[y][x] so the rules can be checked
+// without a window, audio, or a whole GameState.
+
+// Whether `shape` placed at `pos` leaves the board or overlaps a filled cell.
+fn collides(board: &[Vec<Cell>], shape: &[[i32; 2]; 4], pos: (i32, i32)) -> bool {
+    shape.iter().any(|&[dx, dy]| {
+        let (x, y) = (pos.0 + dx, pos.1 + dy);
+        x < 0 || y < 0 || board.get(y as usize).and_then(|row| row.get(x as usize)).is_none_or(|cell| cell.is_some())
+    })
+}
+
+// Indices of every completely filled row, top to bottom.
+fn full_rows(board: &[Vec<Cell>]) -> Vec<usize> {
+    (0..board.len()).filter(|&y| board[y].iter().all(|cell| cell.is_some())).collect()
+}
+
+// Removes `rows` and drops everything above them down, adding empty rows at the top.
+fn remove_rows(board: &mut Vec<Vec<Cell>>, rows: &[usize]) {
+    let (width, height) = (board[0].len(), board.len());
+    let mut kept: Vec<Vec<Cell>> = (0..height).filter(|y| !rows.contains(y)).map(|y| board[y].clone()).collect();
+    while kept.len() < height {
+        kept.insert(0, vec![None; width]);
+    }
+    *board = kept;
+}
+
+// Shifts the whole board up one row and adds a garbage row at the bottom with a hole at `hole`.
+// Returns false if that pushed blocks out through the ceiling.
+fn push_garbage_row(board: &mut Vec<Vec<Cell>>, hole: usize) -> bool {
+    let width = board[0].len();
+    let overflowed = board[0].iter().any(|cell| cell.is_some());
+    board.remove(0);
+    board.push((0..width).map(|x| if x == hole { None } else { Some((GARBAGE_COLOR, TetrominoType::Garbage, 0)) }).collect());
+    !overflowed
+}
+
+// A placement's cells with rows counted from its own top, sorted, so a drop into the
+// same columns with the same shape matches however the piece was steered there.
+fn footprint(t: &Tetromino) -> [[i32; 2]; 4] {
+    let top = t.shape.iter().map(|&[_, dy]| dy).min().unwrap_or(0);
+    let mut cells = t.shape.map(|[dx, dy]| [t.pos.0 + dx, dy - top]);
+    cells.sort();
+    cells
+}
+
+// Fewest presses that steer a freshly spawned piece over `target` on an empty board.
+// A tap, a rotation, and a shift held to the wall each count as one. None if unreachable.
+fn finesse_minimum(width: usize, height: usize, spawn: Tetromino, target: &[[i32; 2]; 4]) -> Option<u32> {
+    let board = vec![vec![None; width]; height];
+    let mut seen = HashSet::from([(spawn.pos, spawn.rotation)]);
+    let mut queue = VecDeque::from([(spawn, 0)]);
+    while let Some((t, presses)) = queue.pop_front() {
+        if footprint(&t) == *target {
+            return Some(presses);
+        }
+        let shift = |dx: i32| {
+            let pos = (t.pos.0 + dx, t.pos.1);
+            (!collides(&board, &t.shape, pos)).then_some(Tetromino { pos, ..t })
+        };
+        let slide = |dx: i32| {
+            let mut s = t;
+            while !collides(&board, &s.shape, (s.pos.0 + dx, s.pos.1)) {
+                s.pos.0 += dx;
+            }
+            s
+        };
+        let moves = [
+            shift(-1),
+            shift(1),
+            Some(slide(-1)),
+            Some(slide(1)),
+            rotated(&board, &t, true),
+            rotated(&board, &t, false),
+            rotated_180(&board, &t),
+        ];
+        for next in moves.into_iter().flatten() {
+            if seen.insert((next.pos, next.rotation)) {
+                queue.push_back((next, presses + 1));
+            }
+        }
+    }
+    None
+}
+
+// The bounding box (min_x, min_y, max_x, max_y) of every locked piece, by piece id.
+// Built once per scan so each 4x4 candidate is checked without walking the whole board.
+fn piece_bounds(board: &[Vec<Cell>]) -> HashMap<u32, (usize, usize, usize, usize)> {
+    let mut bounds: HashMap<u32, (usize, usize, usize, usize)> = HashMap::new();
+    for (y, row) in board.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            let Some((_, t_type, id)) = *cell else { continue };
+            if t_type == TetrominoType::Garbage {
+                continue;
+            }
+            let b = bounds.entry(id).or_insert((x, y, x, y));
+            *b = (b.0.min(x), b.1.min(y), b.2.max(x), b.3.max(y));
+        }
+    }
+    bounds
+}
+
+// Whether every locked cell of each piece in `pieces` lies inside the 4x4 region at (x, y).
+fn pieces_within(bounds: &HashMap<u32, (usize, usize, usize, usize)>, pieces: &[u32], x: usize, y: usize) -> bool {
+    pieces.iter().all(|id| {
+        bounds.get(id).is_some_and(|&(min_x, min_y, max_x, max_y)| {
+            min_x >= x && min_y >= y && max_x < x + 4 && max_y < y + 4
+        })
+    })
+}
+
+// A bonus square found by find_new_squares.
+struct NewSquare {
+    x: usize,
+    y: usize,
+    is_gold: bool, // Every piece in it is the same type.
+    original: [[(Color, TetrominoType, u32); 4]; 4],
+}
+
+// Top-left corners of 4x4 regions that are at least three-quarters full and could
+// still become a bonus square: no garbage, no piece that already scored one, and
+// every piece present lies entirely inside the region.
+fn square_opportunities(board: &[Vec<Cell>], squared_pieces: &HashSet<u32>) -> Vec<(usize, usize)> {
+    let (width, height) = (board[0].len(), board.len());
+    let bounds = piece_bounds(board);
+    let mut found = vec![];
+    for y in 0..(height - 3) {
+        for x in 0..(width - 3) {
+            let mut filled = 0;
+            let mut usable = true;
+            let mut pieces_in_region = vec![];
+            for row in &board[y..y + 4] {
+                for cell in &row[x..x + 4] {
+                    match *cell {
+                        Some((_, TetrominoType::Garbage, _)) => usable = false,
+                        Some((_, _, id)) => {
+                            filled += 1;
+                            if squared_pieces.contains(&id) {
+                                usable = false;
+                            }
+                            if !pieces_in_region.contains(&id) {
+                                pieces_in_region.push(id);
+                            }
+                        }
+                        None => {}
+                    }
+                }
+            }
+            if !usable || !(12..16).contains(&filled) {
+                continue;
+            }
+            if pieces_within(&bounds, &pieces_in_region, x, y) {
+                found.push((x, y));
+            }
+        }
+    }
+    found
+}
+
+// --- Square Detection ---
+// A candidate counts when every cell in it is full (and not garbage) and for every piece present,
+// all its locked cells lie entirely within the candidate. Pieces in each new square are added
+// to `squared_pieces` so they never score twice.
+fn find_new_squares(board: &[Vec<Cell>], squared_pieces: &mut HashSet<u32>) -> Vec<NewSquare> {
+    let (width, height) = (board[0].len(), board.len());
+    let bounds = piece_bounds(board);
+    let mut found = vec![];
+    for y in 0..(height - 3) {
+        for x in 0..(width - 3) {
+            let mut all_filled = true;
+            let mut original: [[(Color, TetrominoType, u32); 4]; 4] =
+                [[(BLACK_COLOR, TetrominoType::I, 0); 4]; 4];
+            for (dy, orig_row) in original.iter_mut().enumerate() {
+                for (dx, orig_cell) in orig_row.iter_mut().enumerate() {
+                    if let Some(cell) = board[y + dy][x + dx] {
+                        if cell.1 == TetrominoType::Garbage {
+                            all_filled = false;
+                            break;
+                        }
+                        *orig_cell = cell;
+                    } else {
+                        all_filled = false;
+                        break;
+                    }
+                }
+                if !all_filled {
+                    break;
+                }
+            }
+            if !all_filled {
+                continue;
+            }
+            let mut pieces_in_region = vec![];
+            for row in &original {
+                for &(_, _t, id) in row {
+                    if !pieces_in_region.contains(&id) {
+                        pieces_in_region.push(id);
+                    }
+                }
+            }
+            if !pieces_within(&bounds, &pieces_in_region, x, y) {
+                continue;
+            }
+            let mut types = vec![];
+            for &pid in &pieces_in_region {
+                if let Some(&(_, t, _)) = original.iter().flatten().find(|cell| cell.2 == pid) {
+                    types.push(t);
+                }
+            }
+            let all_same = types.iter().all(|&t| t == types[0]);
+            // Squares stay ordinary blocks afterwards, so don't score the same pieces twice.
+            if pieces_in_region.iter().any(|id| squared_pieces.contains(id)) {
+                continue;
+            }
+            squared_pieces.extend(&pieces_in_region);
+            found.push(NewSquare {
+                x,
+                y,
+                is_gold: all_same,
+                original,
+            });
+        }
+    }
+    found
+}
+
+struct GameState {
+    // Indexed board[y][x], sized width x height when a game starts.
+    board: Vec<Vec<Cell>>,
+    width: usize,
+    height: usize,
+    tetromino: Option<Tetromino>,
+    next_queue: VecDeque<Tetromino>,
+    hold_tetromino: Option<Tetromino>,
+    hold_used: bool,
+    hold_enabled: bool, // Copied from the config (or replay) when a game starts.
+    handling: Handling, // Likewise copied, since DAS and ARR change how inputs play out.
+    confirm_hard_drop: bool, // Likewise copied from the config (or replay).
+    mirrored: bool,          // Likewise; flips the board in draw and swaps left/right input.
+    clock_pauses_on_clear: bool, // Likewise copied, since it changes when a countdown runs out.
+    hard_drop_tap_timer: f32, // Time left to confirm a hard drop with a second tap.
+    ghost_enabled: bool, // Set when a game starts; Hard never shows the ghost.
+
+    started: bool,
+    paused: bool,
+    game_over: bool,
+    end_reason: EndReason, // Set by end_game; only meaningful once game_over is set.
+    lines_cleared: u32,
+    score: u32,
+    level: u32,
+    start_level: u32, // Level the run began on; clears count up from here.
+    combo: i32, // Consecutive line-clearing locks minus one; -1 when no streak.
+
+    game_mode: GameMode,
+    difficulty: Difficulty,
+    board_size: BoardSize,
+    time_remaining: f32, // Countdown for GameMode::Timed and GameMode::Ultra.
+    race_time: f32,      // Clock for modes with a goal (Sprint, Dig, Marathon).
+    garbage_timer: f32,  // Countdown to the next rising row in GameMode::Survival.
+    play_time: f32,      // Time spent playing, excluding pauses and line-clear freezes.
+
+    left_timer: f32,
+    right_timer: f32,
+    fall_timer: f32,
+
+    // Time left before a grounded piece locks, and how many times moving or
+    // rotating has pushed that back for the current piece.
+    lock_delay_timer: f32,
+    lock_delay_resets: u32,
+
+    line_clear_timer: f32,
+    clearing_lines: Vec<usize>,
+
+    active_squares: Vec<SquareEffect>,
+    squared_pieces: HashSet<u32>, // Piece ids already scored in a 4x4 square.
+    lock_flashes: Vec<LockFlash>,
+    particles: Vec<Particle>,
+
+    last_move_rotation: bool, // The active piece's last successful move was a rotation.
+    t_spin: bool,             // The piece that just locked was a T-spin.
+    t_spin_popup_timer: f32,
+    back_to_back: bool, // The last line clear was a Tetris or T-spin.
+    high_score_beaten: bool, // This run has passed the saved high score.
+    high_score_banner_timer: f32,
+
+    next_piece_id: u32, // For unique locked piece tagging.
+    lock_times: HashMap<u32, f32>, // play_time at which each piece id locked.
+    undo_history: VecDeque<PracticeSnapshot>, // Practice mode only, newest last.
+    finesse_presses: u32, // Move and rotate presses spent on the active piece.
+    finesse_errors: u32,  // Pieces placed with more presses than needed.
+
+    mus_mgr: MusicManager,
+
+    // Statistics counter for spawned tetrominoes.
+    piece_statistics: HashMap<TetrominoType, u32>,
+    pieces_since: HashMap<TetrominoType, u32>, // Pieces dealt since each type last spawned.
+
+    // 7-bag randomizer: every type appears once per shuffled bag.
+    piece_bag: Vec<TetrominoType>,
+    // Seed for reproducible piece sequences; None picks a fresh one each game.
+    seed: Option<u64>,
+    rng: StdRng,
+
+    frame: u32,     // Frames simulated in the current game.
+    replay: Replay, // Inputs recorded so far, saved on game over.
+    sim_accumulator: f32,
+    keyboard: KeyboardInput,
+    playback: Option<ReplayInput>, // Set while watching a replay instead of playing.
+
+    config: Config, // The active profile; written back into `profiles` on save.
+    profiles: Profiles,
+    leaderboard: Vec<ScoreEntry>,
+    show_leaderboard: bool,
+    volume_display_timer: f32, // Time left to show the volume bar after a change.
+    stats_reset_timer: f32, // Time left to show the "Stats reset" note.
+    menu_screen: MenuScreen,
+    menu_selection: usize,  // Index into menu_screen's options on the start screen.
+    editing_name: bool,     // Enter on the Player row; every key types until Enter or Escape.
+    rebinding: Option<GameAction>, // Waiting for the next key press to bind to this action.
+    pause_selection: usize, // Index into PauseOption::ALL while paused.
+    confirm_quit: bool,     // The window was closed mid-game; waiting for Y/N.
+    quit_confirmed: bool,   // Safe to close the window now.
+    show_debug: bool,       // F3 frame-time overlay.
+    resize_timer: f32,      // How long the window has differed from the saved size.
+    persist: bool,          // Write profiles, history, the leaderboard and replays; off in tests.
+}
+
+impl GameState {
+    pub fn new() -> Self {
+        let mut piece_statistics = HashMap::new();
+        // Initialize counter for the main tetromino types.
+        for &piece in &[
+            TetrominoType::I,
+            TetrominoType::O,
+            TetrominoType::T,
+            TetrominoType::S,
+            TetrominoType::Z,
+            TetrominoType::J,
+            TetrominoType::L,
+        ] {
+            piece_statistics.insert(piece, 0);
+        }
+
+        let profiles = load_profiles();
+        let config = profiles.list[profiles.active].clone();
+        let mut mus_mgr = MusicManager::new(config.custom_music_only);
+        mus_mgr.set_music_volume(config.music_volume);
+        mus_mgr.set_muted(config.muted);
+        mus_mgr.set_sfx_volume(config.sfx_volume);
+        mus_mgr.set_sfx_muted(config.sfx_muted);
+
+        Self {
+            board: vec![vec![None; GRID_WIDTH]; GRID_HEIGHT],
+            width: GRID_WIDTH,
+            height: GRID_HEIGHT,
+            tetromino: None,
+            next_queue: VecDeque::new(),
+            hold_tetromino: None,
+            hold_used: false,
+            hold_enabled: true,
+            handling: Handling::default(),
+            confirm_hard_drop: false,
+            mirrored: false,
+            clock_pauses_on_clear: false,
+            hard_drop_tap_timer: 0.0,
+            ghost_enabled: true,
+            started: false,
+            paused: false,
+            game_over: false,
+            end_reason: EndReason::default(),
+            lines_cleared: 0,
+            score: 0,
+            level: 1,
+            start_level: 1,
+            combo: -1,
+            game_mode: GameMode::Classic,
+            difficulty: Difficulty::Normal,
+            board_size: BoardSize::Standard,
+            time_remaining: 0.0,
+            race_time: 0.0,
+            garbage_timer: 0.0,
+            play_time: 0.0,
+            left_timer: 0.0,
+            right_timer: 0.0,
+            fall_timer: 0.0,
+            lock_delay_timer: 0.0,
+            lock_delay_resets: 0,
+            line_clear_timer: 0.0,
+            clearing_lines: Vec::new(),
+            active_squares: Vec::new(),
+            squared_pieces: HashSet::new(),
+            lock_flashes: Vec::new(),
+            particles: Vec::new(),
+            last_move_rotation: false,
+            t_spin: false,
+            t_spin_popup_timer: 0.0,
+            high_score_beaten: false,
+            high_score_banner_timer: 0.0,
+            back_to_back: false,
+            next_piece_id: 1,
+            undo_history: VecDeque::new(),
+            finesse_presses: 0,
+            finesse_errors: 0,
+            lock_times: HashMap::new(),
+            mus_mgr,
+            piece_statistics,
+            pieces_since: HashMap::new(),
+            piece_bag: Vec::new(),
+            seed: None,
+            rng: StdRng::from_entropy(),
+            frame: 0,
+            replay: Replay::default(),
+            sim_accumulator: 0.0,
+            keyboard: KeyboardInput::default(),
+            playback: None,
+            config,
+            profiles,
+            leaderboard: load_json(LEADERBOARD_PATH),
+            show_leaderboard: false,
+            volume_display_timer: 0.0,
+            stats_reset_timer: 0.0,
+            menu_screen: MenuScreen::Main,
+            menu_selection: 0,
+            editing_name: false,
+            rebinding: None,
+            pause_selection: 0,
+            confirm_quit: false,
+            show_debug: false,
+            resize_timer: 0.0,
+            quit_confirmed: false,
+            persist: !cfg!(test),
+        }
+    }
+
+    pub fn start_game(&mut self) {
+        // Names from a hand-edited profiles.json get the same cleanup as typed ones.
+        self.tidy_name();
+        self.started = true;
+        self.game_over = false;
+        self.paused = false;
+        self.lines_cleared = 0;
+        self.score = 0;
+        self.level = self.difficulty.start_level();
+        self.combo = -1;
+        match self.game_mode {
+            GameMode::Timed => self.time_remaining = TIMED_MODE_SECONDS,
+            GameMode::Ultra => {
+                self.time_remaining = ULTRA_MODE_SECONDS;
+                self.level = ULTRA_LEVEL;
+            }
+            _ => {}
+        }
+        self.start_level = self.level;
+        self.ghost_enabled = self.config.ghost_enabled && self.difficulty != Difficulty::Hard;
+        self.race_time = 0.0;
+        self.play_time = 0.0;
+        self.garbage_timer = SURVIVAL_RISE_START;
+        let (width, height) = self.board_size.dims();
+        let cells = self.game_mode.cell_scale();
+        // Keep at least four columns so a flat I piece still fits on a Big Mini board.
+        (self.width, self.height) = ((width / cells).max(4), height / cells);
+        self.board = vec![vec![None; self.width]; self.height];
+        self.hold_tetromino = None;
+        self.hold_used = false;
+        self.hard_drop_tap_timer = 0.0;
+        self.line_clear_timer = 0.0;
+        self.lock_delay_timer = 0.0;
+        self.lock_delay_resets = 0;
+        self.clearing_lines.clear();
+        self.undo_history.clear();
+        self.finesse_presses = 0;
+        self.finesse_errors = 0;
+        self.active_squares.clear();
+        self.squared_pieces.clear();
+        self.lock_flashes.clear();
+        self.particles.clear();
+        self.last_move_rotation = false;
+        self.t_spin = false;
+        self.t_spin_popup_timer = 0.0;
+        self.high_score_beaten = false;
+        self.high_score_banner_timer = 0.0;
+        self.back_to_back = false;
+        self.next_piece_id = 1;
+        self.lock_times.clear();
+
+        // Reset statistics at the start of a new game.
+        self.piece_statistics.clear();
+        for &piece in &[
+            TetrominoType::I,
+            TetrominoType::O,
+            TetrominoType::T,
+            TetrominoType::S,
+            TetrominoType::Z,
+            TetrominoType::J,
+            TetrominoType::L,
+        ] {
+            self.piece_statistics.insert(piece, 0);
+            self.pieces_since.insert(piece, 0);
+        }
+
+        // Start every game with a fresh bag, reseeded so a set seed replays the same pieces.
+        // Random runs still pick a concrete seed so their replay can deal the same bag.
+        let seed = match &self.playback {
+            Some(playback) => {
+                self.level = playback.replay.start_level;
+                self.start_level = self.level;
+                self.hold_enabled = playback.replay.hold_enabled;
+                self.handling = playback.replay.handling;
+                self.confirm_hard_drop = playback.replay.confirm_hard_drop;
+                self.mirrored = playback.replay.mirrored;
+                self.clock_pauses_on_clear = playback.replay.clock_pauses_on_clear;
+                playback.replay.seed
+            }
+            None => {
+                self.hold_enabled = self.config.hold_enabled;
+                self.confirm_hard_drop = self.config.confirm_hard_drop;
+                self.mirrored = self.config.mirrored;
+                self.clock_pauses_on_clear = self.config.clock_pauses_on_clear;
+                self.handling = Handling {
+                    das: self.config.handling.das.max(0.0),
+                    arr: self.config.handling.arr.max(0.0),
+                    soft_drop_speed: self.config.handling.soft_drop_speed.clamp(SOFT_DROP_SPEED_RANGE.0, SOFT_DROP_SPEED_RANGE.1),
+                    instant_soft_drop: self.config.handling.instant_soft_drop,
+                };
+                self.seed.unwrap_or_else(|| ::rand::thread_rng().gen())
+            }
+        };
+        self.rng = StdRng::seed_from_u64(seed);
+        if self.game_mode == GameMode::Dig {
+            self.fill_garbage(DIG_GARBAGE_ROWS);
+        }
+        self.piece_bag.clear();
+        self.frame = 0;
+        self.sim_accumulator = 0.0;
+        self.keyboard = KeyboardInput::default();
+        self.replay = Replay {
+            version: REPLAY_VERSION,
+            mode: self.game_mode,
+            difficulty: self.difficulty,
+            board_size: self.board_size,
+            start_level: self.level,
+            hold_enabled: self.hold_enabled,
+            handling: self.handling,
+            confirm_hard_drop: self.confirm_hard_drop,
+            mirrored: self.mirrored,
+            clock_pauses_on_clear: self.clock_pauses_on_clear,
+            seed,
+            inputs: Vec::new(),
+        };
+        let curr_type = self.next_from_bag();
+
+        self.tetromino = Some(Tetromino::new(curr_type, self.config.color_scheme, self.width));
+        self.count_spawn(curr_type);
+
+        self.next_queue.clear();
+        let queue_size = self.config.next_queue_size.clamp(NEXT_QUEUE_SIZE_RANGE.0, NEXT_QUEUE_SIZE_RANGE.1);
+        for _ in 0..queue_size {
+            let t_type = self.next_from_bag();
+            self.next_queue.push_back(Tetromino::new(t_type, self.config.color_scheme, self.width));
+        }
+        self.mus_mgr.play_choice(self.config.music_choice(self.game_mode));
+    }
+
+    // Pops the next piece type, refilling and shuffling all seven types when the bag runs out.
+    fn next_from_bag(&mut self) -> TetrominoType {
+        if self.piece_bag.is_empty() {
+            self.piece_bag.extend_from_slice(&[
+                TetrominoType::I,
+                TetrominoType::O,
+                TetrominoType::T,
+                TetrominoType::S,
+                TetrominoType::Z,
+                TetrominoType::J,
+                TetrominoType::L,
+            ]);
+            self.piece_bag.shuffle(&mut self.rng);
+        }
+        self.piece_bag.pop().unwrap()
+    }
+
+    pub fn check_collision(&self, shape: &[[i32; 2]; 4], pos: (i32, i32)) -> bool {
+        collides(&self.board, shape, pos)
+    }
+
+    // How many rows `t` can fall before it lands. Never more than the board height.
+    fn drop_distance(&self, t: &Tetromino) -> i32 {
+        (0..self.height as i32)
+            .take_while(|&d| !self.check_collision(&t.shape, (t.pos.0, t.pos.1 + d + 1)))
+            .count() as i32
+    }
+
+    pub fn lock_tetromino(&mut self) {
+        if self.game_mode == GameMode::Practice {
+            self.save_undo_snapshot();
+        }
         if let Some(tetro) = self.tetromino {
+            // Only Practice shows the count, so other modes skip the search on every lock.
+            if self.game_mode == GameMode::Practice {
+                self.check_finesse(&tetro);
+            }
+            self.t_spin = self.is_t_spin(&tetro);
+            if self.t_spin {
+                self.t_spin_popup_timer = T_SPIN_POPUP_DURATION;
+            }
             let id = self.next_piece_id;
             self.next_piece_id += 1;
+            self.lock_times.insert(id, self.play_time);
             for &[dx, dy] in &tetro.shape {
                 let x = tetro.pos.0 + dx;
                 let y = tetro.pos.1 + dy;
-                if x >= 0 && x < GRID_WIDTH as i32 && y >= 0 && y < GRID_HEIGHT as i32 {
+                if x >= 0 && x < self.width as i32 && y >= 0 && y < self.height as i32 {
                     self.board[y as usize][x as usize] = Some((tetro.color, tetro.t_type, id));
                 }
             }
         }
-        let mut full_rows = Vec::new();
-        for (i, row) in self.board.iter().enumerate() {
-            if row.iter().all(|cell| cell.is_some()) {
-                full_rows.push(i);
-            }
-        }
+        let full_rows = full_rows(&self.board);
         if !full_rows.is_empty() {
+            // The tone starts with the clear animation, pitched for the combo this clear makes.
+            self.mus_mgr.play_sfx(&line_clear_sfx(full_rows.len(), self.combo + 1));
             self.clearing_lines = full_rows;
-            self.line_clear_timer = 0.27;
+            self.line_clear_timer = LINE_CLEAR_DURATION;
         } else {
+            self.combo = -1;
+            if self.t_spin {
+                self.score += T_SPIN_POINTS[0] * self.level;
+                self.t_spin = false;
+            }
             self.spawn_new_tetromino();
             self.check_for_4x4_squares();
         }
     }
 
+    // Counts a finesse error when the piece took more presses than the fewest that place it.
+    // Only spots a straight drop could reach are judged, since tucks and spins need extra moves.
+    fn check_finesse(&mut self, tetro: &Tetromino) {
+        let droppable = (0..tetro.pos.1).all(|y| !self.check_collision(&tetro.shape, (tetro.pos.0, y)));
+        if !droppable {
+            return;
+        }
+        let spawn = Tetromino::new(tetro.t_type, self.config.color_scheme, self.width);
+        if finesse_minimum(self.width, self.height, spawn, &footprint(tetro)).is_some_and(|min| self.finesse_presses > min) {
+            self.finesse_errors += 1;
+        }
+    }
+
+    // Remembers the state before the active piece locks, dropping the oldest past the limit.
+    fn save_undo_snapshot(&mut self) {
+        let Some(tetro) = self.tetromino else { return };
+        if self.undo_history.len() >= PRACTICE_UNDO_DEPTH {
+            self.undo_history.pop_front();
+        }
+        self.undo_history.push_back(PracticeSnapshot {
+            board: self.board.clone(),
+            piece: tetro.t_type,
+            next_queue: self.next_queue.clone(),
+            hold_tetromino: self.hold_tetromino,
+            hold_used: self.hold_used,
+            piece_bag: self.piece_bag.clone(),
+            rng: self.rng.clone(),
+            score: self.score,
+            lines_cleared: self.lines_cleared,
+            level: self.level,
+            combo: self.combo,
+            back_to_back: self.back_to_back,
+            piece_statistics: self.piece_statistics.clone(),
+            pieces_since: self.pieces_since.clone(),
+            squared_pieces: self.squared_pieces.clone(),
+            finesse_errors: self.finesse_errors,
+        });
+    }
+
+    // Practice mode: puts the game back to just before the last lock, with that piece
+    // back at the top of the board.
+    fn undo_lock(&mut self) {
+        let Some(snap) = self.undo_history.pop_back() else { return };
+        self.board = snap.board;
+        self.tetromino = Some(Tetromino::new(snap.piece, self.config.color_scheme, self.width));
+        self.next_queue = snap.next_queue;
+        self.hold_tetromino = snap.hold_tetromino;
+        self.hold_used = snap.hold_used;
+        self.piece_bag = snap.piece_bag;
+        self.rng = snap.rng;
+        self.score = snap.score;
+        self.lines_cleared = snap.lines_cleared;
+        self.level = snap.level;
+        self.combo = snap.combo;
+        self.back_to_back = snap.back_to_back;
+        self.piece_statistics = snap.piece_statistics;
+        self.pieces_since = snap.pieces_since;
+        self.squared_pieces = snap.squared_pieces;
+        self.finesse_errors = snap.finesse_errors;
+        self.finesse_presses = 0;
+        self.active_squares.clear();
+        self.lock_flashes.clear();
+        self.last_move_rotation = false;
+        self.t_spin = false;
+        self.hard_drop_tap_timer = 0.0;
+        self.fall_timer = 0.0;
+        self.lock_delay_timer = 0.0;
+        self.lock_delay_resets = 0;
+    }
+
+    // A T piece whose last move was a rotation and that has at least three of
+    // the four cells diagonal to its center blocked (walls and floor count).
+    fn is_t_spin(&self, tetro: &Tetromino) -> bool {
+        if tetro.t_type != TetrominoType::T || !self.last_move_rotation {
+            return false;
+        }
+        let [px, py] = TETROMINO_ROTATION_OFFSETS[TetrominoType::T as usize];
+        let (cx, cy) = (tetro.pos.0 + px, tetro.pos.1 + py);
+        let blocked = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+            .iter()
+            .filter(|&&(dx, dy)| {
+                let (x, y) = (cx + dx, cy + dy);
+                x < 0 || x >= self.width as i32 || y < 0 || y >= self.height as i32
+                    || self.board[y as usize][x as usize].is_some()
+            })
+            .count();
+        blocked >= 3
+    }
+
+    // Bursts each cleared cell into a few particles in its own color, capped at MAX_PARTICLES.
+    fn spawn_clear_particles(&mut self) {
+        // Cosmetic only, so it uses its own RNG and leaves the seeded piece sequence alone.
+        let mut rng = ::rand::thread_rng();
+        for &row in &self.clearing_lines {
+            for (x, cell) in self.board[row].iter().enumerate() {
+                let Some((color, _, _)) = cell else { continue };
+                for _ in 0..PARTICLES_PER_CELL {
+                    if self.particles.len() >= MAX_PARTICLES {
+                        return;
+                    }
+                    self.particles.push(Particle {
+                        x: x as f32 + 0.5,
+                        y: row as f32 + 0.5,
+                        vx: rng.gen_range(-6.0..6.0),
+                        vy: rng.gen_range(-12.0..-2.0),
+                        color: *color,
+                        life: PARTICLE_LIFETIME * rng.gen_range(0.6..1.0),
+                    });
+                }
+            }
+        }
+    }
+
     pub fn clear_lines_delayed(&mut self) {
-        let mut new_board: Vec<[Option<(Color, TetrominoType, u32)>; GRID_WIDTH]> = Vec::new();
-        for (i, row) in self.board.iter().enumerate() {
-            if self.clearing_lines.contains(&i) { continue; }
-            new_board.push(*row);
+        self.spawn_clear_particles();
+        remove_rows(&mut self.board, &self.clearing_lines);
+        let cleared = self.clearing_lines.len();
+        let mut points = if self.t_spin {
+            T_SPIN_POINTS[cleared.min(3)]
+        } else {
+            LINE_CLEAR_POINTS[cleared.min(4)]
+        } * self.level;
+        // Difficult clears chain into back-to-back; any other clear breaks it.
+        let difficult = self.t_spin || cleared >= 4;
+        if difficult && self.back_to_back {
+            points = points * BACK_TO_BACK_NUMERATOR / BACK_TO_BACK_DENOMINATOR;
         }
-        while new_board.len() < GRID_HEIGHT {
-            new_board.insert(0, [None; GRID_WIDTH]);
+        self.back_to_back = difficult;
+        self.score += points;
+        self.t_spin = false;
+        self.combo += 1;
+        if self.combo > 0 {
+            self.score += 50 * self.combo as u32 * self.level;
+        }
+        self.lines_cleared += cleared as u32;
+        // Ultra stays at its fixed level for the whole run.
+        if self.game_mode != GameMode::Ultra {
+            self.level = self.start_level + self.lines_cleared / LINES_PER_LEVEL;
         }
-        self.board = new_board.try_into().unwrap();
-        self.lines_cleared += self.clearing_lines.len() as u32;
         self.clearing_lines.clear();
 
-        if let Some(next) = self.next_tetromino {
-            if self.check_collision(&next.shape, next.pos) {
-                self.game_over = true;
-                self.started = false;
-                return;
-            }
+        if self.goal_reached() {
+            self.end_game(EndReason::GoalReached);
+            return;
         }
+
+        // Spawning decides whether the run tops out, so Zen can make room first.
         self.spawn_new_tetromino();
         self.check_for_4x4_squares();
     }
 
+    // Ends the current run and saves it if it beats the record for this mode.
+    pub fn end_game(&mut self, reason: EndReason) {
+        self.game_over = true;
+        self.started = false;
+        self.end_reason = reason;
+        // Watching a replay never touches records, the leaderboard, or saved replays.
+        if self.playback.is_some() {
+            return;
+        }
+        let goal_reached = reason == EndReason::GoalReached;
+        let mut config_changed = false;
+        if self.run_keeps_scores() {
+            let record = self.config.records.entry(self.board_size.record_key(self.game_mode)).or_default();
+            if self.score > record.high_score {
+                record.high_score = self.score;
+                record.line_count = self.lines_cleared;
+                config_changed = true;
+            }
+            if goal_reached && record.best_time.is_none_or(|best| self.race_time < best) {
+                record.best_time = Some(self.race_time);
+                config_changed = true;
+            }
+        }
+        // The controls text is shown through the first game, then hidden until asked for.
+        if self.config.show_controls.is_none() {
+            self.config.show_controls = Some(false);
+            config_changed = true;
+        }
+        if config_changed {
+            self.save_config();
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let timestamp = now.as_secs();
+        if self.persist && self.config.history_csv {
+            self.append_history(timestamp);
+        }
+        // Undo can reach any score, so Practice runs stay off the leaderboard, like unscored Zen.
+        if self.run_keeps_scores() && self.game_mode != GameMode::Practice {
+            self.leaderboard.push(ScoreEntry {
+                name: self.config.player_name.clone(),
+                score: self.score,
+                lines: self.lines_cleared,
+                mode: self.board_size.record_key(self.game_mode),
+                timestamp,
+            });
+            self.leaderboard.sort_by_key(|entry| Reverse(entry.score));
+            self.leaderboard.truncate(LEADERBOARD_SIZE);
+            if self.persist {
+                save_json(LEADERBOARD_PATH, &self.leaderboard);
+            }
+        }
+        if !self.persist {
+            return;
+        }
+
+        if let Err(e) = fs::create_dir_all(REPLAY_DIR) {
+            eprintln!("Failed to create {}: {}", REPLAY_DIR, e);
+            return;
+        }
+        // Milliseconds, so two games ending in the same second don't overwrite each other.
+        let replay_path = format!("{}/{}-{}.replay", REPLAY_DIR, self.game_mode.name().to_lowercase(), now.as_millis());
+        save_json(&replay_path, &self.replay);
+    }
+
+    // Whether this run can set records. A countdown that stopped during clears gave the
+    // run extra time, so those Timed and Ultra runs stay off records and the leaderboard.
+    fn run_keeps_scores(&self) -> bool {
+        self.game_mode.keeps_scores() && !(self.clock_pauses_on_clear && self.game_mode.has_countdown())
+    }
+
+    // Logs this frame's presses and releases of the action keys.
+    fn record_inputs(&mut self, input: &InputFrame) {
+        for action in GameAction::ALL {
+            if input.pressed(action) {
+                self.replay.inputs.push((self.frame, InputAction::Press(action)));
+            }
+            if input.released(action) {
+                self.replay.inputs.push((self.frame, InputAction::Release(action)));
+            }
+        }
+    }
+
+    // Starts watching a saved run, dealing from its seed and mode.
+    pub fn start_replay(&mut self, playback: ReplayInput) {
+        self.game_mode = playback.replay.mode;
+        self.difficulty = playback.replay.difficulty;
+        self.board_size = playback.replay.board_size;
+        self.playback = Some(playback);
+        self.start_game();
+    }
+
+    // Whether the current mode has reached its goal and the run is won.
+    fn goal_reached(&self) -> bool {
+        match self.game_mode {
+            GameMode::Sprint => self.lines_cleared >= SPRINT_LINES,
+            GameMode::Marathon => self.lines_cleared >= MARATHON_LINES,
+            GameMode::Dig => !self.board.iter().flatten().any(|cell| {
+                matches!(cell, Some((_, TetrominoType::Garbage, _)))
+            }),
+            _ => false,
+        }
+    }
+
+    // Fills the bottom rows with garbage, each solid except for one random hole.
+    fn fill_garbage(&mut self, rows: usize) {
+        for y in self.height - rows..self.height {
+            let hole = self.rng.gen_range(0..self.width);
+            for x in 0..self.width {
+                if x != hole {
+                    self.board[y][x] = Some((GARBAGE_COLOR, TetrominoType::Garbage, 0));
+                }
+            }
+        }
+    }
+
+    // Survival: pushes a garbage row up under the stack. The falling piece is lifted with it
+    // if the new row would overlap it; running out of room either way tops the player out.
+    fn rise_garbage(&mut self) {
+        let hole = self.rng.gen_range(0..self.width);
+        if !push_garbage_row(&mut self.board, hole) {
+            self.end_game(EndReason::TopOut);
+            return;
+        }
+        if let Some(curr) = self.tetromino {
+            if self.check_collision(&curr.shape, curr.pos) {
+                if self.check_collision(&curr.shape, (curr.pos.0, curr.pos.1 - 1)) {
+                    self.end_game(EndReason::TopOut);
+                    return;
+                }
+                self.move_tetromino((0, -1));
+            }
+        }
+        self.score += SURVIVAL_ROW_POINTS * self.level;
+    }
+
+    // Height of the stack in standard rows, counting Big mode's doubled cells.
+    fn stack_height(&self) -> usize {
+        let top = self.board.iter().position(|row| row.iter().any(|cell| cell.is_some())).unwrap_or(self.height);
+        (self.height - top) * self.game_mode.cell_scale()
+    }
+
+    // Seconds between Survival's rising rows, shrinking the longer the player lasts.
+    fn survival_rise_interval(&self) -> f32 {
+        (SURVIVAL_RISE_START - self.play_time * SURVIVAL_RISE_SPEEDUP).max(SURVIVAL_RISE_MIN)
+    }
+
     pub fn spawn_new_tetromino(&mut self) {
         if !self.started { return; }
-        if let Some(next_t) = self.next_tetromino {
+        if let Some(&next_t) = self.next_queue.front() {
+            // Zen never tops out: wipe the top of the stack to make room instead.
+            if self.game_mode == GameMode::Zen && self.check_collision(&next_t.shape, next_t.pos) {
+                for row in self.board.iter_mut().take(ZEN_CLEAR_ROWS) {
+                    row.fill(None);
+                }
+            }
             if self.check_collision(&next_t.shape, next_t.pos) {
-                self.game_over = true;
-                self.started = false;
+                self.end_game(EndReason::TopOut);
             } else {
+                self.next_queue.pop_front();
                 self.tetromino = Some(next_t);
-                // Increment the statistics for the newly spawned tetromino.
-                *self.piece_statistics.entry(next_t.t_type).or_insert(0) += 1;
-
-                let mut rng = thread_rng();
-                let t_type = match rng.gen_range(0..7) {
-                    0 => TetrominoType::I,
-                    1 => TetrominoType::O,
-                    2 => TetrominoType::T,
-                    3 => TetrominoType::S,
-                    4 => TetrominoType::Z,
-                    5 => TetrominoType::J,
-                    _ => TetrominoType::L,
-                };
-                self.next_tetromino = Some(Tetromino::new(t_type));
+                self.count_spawn(next_t.t_type);
+
+                let t_type = self.next_from_bag();
+                self.next_queue.push_back(Tetromino::new(t_type, self.config.color_scheme, self.width));
                 self.hold_used = false;
+                self.hard_drop_tap_timer = 0.0;
+                self.finesse_presses = 0;
                 self.fall_timer = 0.0;
+                self.lock_delay_timer = 0.0;
+                self.lock_delay_resets = 0;
+                self.last_move_rotation = false;
             }
         }
     }
 
+    // Counts a spawn in the piece statistics and restarts that type's drought.
+    fn count_spawn(&mut self, t_type: TetrominoType) {
+        *self.piece_statistics.entry(t_type).or_insert(0) += 1;
+        for (&piece, count) in self.pieces_since.iter_mut() {
+            if piece != t_type {
+                *count += 1;
+            }
+        }
+        self.pieces_since.insert(t_type, 0);
+    }
+
     // --- Square Detection ---
-    // Only triggers when every cell in a 4x4 candidate is full (and not bonus) and for every piece present,
-    // all its locked cells lie entirely within the candidate.
+    // Scores each new 4x4 square as soon as it forms, so it isn't lost if the run ends mid-blink.
     pub fn check_for_4x4_squares(&mut self) {
-        for y in 0..(GRID_HEIGHT - 3) {
-            for x in 0..(GRID_WIDTH - 3) {
-                let mut all_filled = true;
-                let mut original: [[(Color, TetrominoType, u32); 4]; 4] =
-                    [[(BLACK_COLOR, TetrominoType::I, 0); 4]; 4];
-                for dy in 0..4 {
-                    for dx in 0..4 {
-                        if let Some(cell) = self.board[y + dy][x + dx] {
-                            if cell.1 == TetrominoType::BonusGold || cell.1 == TetrominoType::BonusSilver {
-                                all_filled = false;
-                                break;
-                            }
-                            original[dy][dx] = cell;
-                        } else {
-                            all_filled = false;
-                            break;
-                        }
-                    }
-                    if !all_filled {
-                        break;
-                    }
-                }
-                if !all_filled {
-                    continue;
-                }
-                let mut pieces_in_region = vec![];
-                for row in &original {
-                    for &(_, _t, id) in row {
-                        if !pieces_in_region.contains(&id) {
-                            pieces_in_region.push(id);
-                        }
-                    }
-                }
-                let mut candidate_valid = true;
-                for &pid in &pieces_in_region {
-                    for row in 0..GRID_HEIGHT {
-                        for col in 0..GRID_WIDTH {
-                            if let Some((_col, _t, id)) = self.board[row][col] {
-                                if id == pid {
-                                    if col < x || col >= x + 4 || row < y || row >= y + 4 {
-                                        candidate_valid = false;
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        if !candidate_valid {
-                            break;
-                        }
-                    }
-                    if !candidate_valid {
-                        break;
-                    }
-                }
-                if !candidate_valid {
-                    continue;
-                }
-                let mut types = vec![];
-                for &pid in &pieces_in_region {
-                    'outer: for dy in 0..4 {
-                        for dx in 0..4 {
-                            if original[dy][dx].2 == pid {
-                                types.push(original[dy][dx].1);
-                                break 'outer;
-                            }
-                        }
-                    }
-                }
-                let all_same = types.iter().all(|&t| t == types[0]);
-                if self.active_squares.iter().any(|eff| eff.x == x && eff.y == y) {
-                    continue;
-                }
-                self.active_squares.push(SquareEffect {
-                    x,
-                    y,
-                    is_gold: all_same,
-                    timer: 0.3,
-                    flash_on: true,
-                    blinks_remaining: 6,
-                    original: original,
-                });
-            }
+        for square in find_new_squares(&self.board, &mut self.squared_pieces) {
+            let base = if square.is_gold { GOLD_POINTS } else { SILVER_POINTS };
+            let points = base * self.level * self.difficulty.square_bonus_scale();
+            self.score += points;
+            self.active_squares.push(SquareEffect {
+                x: square.x,
+                y: square.y,
+                is_gold: square.is_gold,
+                points,
+                timer: 0.3,
+                flash_on: true,
+                blinks_remaining: 6,
+                age: 0.0,
+                original: square.original,
+            });
         }
     }
 
     pub fn update_square_effects(&mut self, dt: f32) {
         self.active_squares.retain_mut(|eff| {
+            eff.age += dt;
             eff.timer -= dt;
             if eff.timer <= 0.0 {
                 eff.timer = 0.3;
@@ -505,18 +2692,8 @@ impl GameState {
                 }
             }
             if eff.blinks_remaining == 0 {
-                let bonus_type = if eff.is_gold {
-                    TetrominoType::BonusGold
-                } else {
-                    TetrominoType::BonusSilver
-                };
-                let square_color = if eff.is_gold { GOLD_COLOR } else { SILVER_COLOR };
-                for dy in 0..4 {
-                    for dx in 0..4 {
-                        self.board[eff.y + dy][eff.x + dx] = Some((square_color, bonus_type, 0));
-                    }
-                }
-                self.score += if eff.is_gold { GOLD_POINTS } else { SILVER_POINTS };
+                // The blink only recolors the drawing; the board keeps the original
+                // blocks, so the region plays on as normal pieces.
                 false
             } else {
                 true
@@ -524,107 +2701,147 @@ impl GameState {
         });
     }
 
-    pub fn process_input(&mut self, delta: f32) {
+    // Shifts the held piece once for every ARR period the repeat timer has run past,
+    // so an ARR shorter than a frame still moves several cells. Returns the new timer.
+    fn auto_shift(&mut self, dx: i32, mut timer: f32) -> f32 {
+        while timer <= 0.0 {
+            let Some(curr) = self.tetromino else { break };
+            if self.check_collision(&curr.shape, (curr.pos.0 + dx, curr.pos.1)) {
+                // Against a wall: shift again as soon as there is room, without a backlog.
+                return timer.max(-SIM_STEP);
+            }
+            self.move_tetromino((dx, 0));
+            self.reset_lock_delay();
+            timer += self.handling.arr;
+        }
+        timer
+    }
+
+    pub fn process_input(&mut self, input: &InputFrame, delta: f32) {
+        // Nothing to steer between a lock and the next spawn (e.g. right after the game ends).
+        let Some(curr) = self.tetromino else {
+            return;
+        };
+
+        let steering = [GameAction::MoveLeft, GameAction::MoveRight, GameAction::RotateCw, GameAction::RotateCcw, GameAction::Rotate180];
+        self.finesse_presses += steering.iter().filter(|&&action| input.pressed(action)).count() as u32;
+
+        // With confirmation on, the first tap only arms the drop; a second tap in time drops.
+        self.hard_drop_tap_timer = (self.hard_drop_tap_timer - delta).max(0.0);
+        let mut hard_drop = input.pressed(GameAction::HardDrop);
+        if hard_drop && self.confirm_hard_drop && self.hard_drop_tap_timer <= 0.0 {
+            self.hard_drop_tap_timer = HARD_DROP_DOUBLE_TAP_WINDOW;
+            hard_drop = false;
+        }
+
         // Hard Drop: We use a separate block to avoid mutable/immutable borrow conflict.
-        if is_key_pressed(KeyCode::Up) {
-            loop {
-                let can_move_down = {
-                    if let Some(ref t) = self.tetromino {
-                        !self.check_collision(&t.shape, (t.pos.0, t.pos.1 + 1))
-                    } else {
-                        false
-                    }
-                };
-                if !can_move_down { break; }
-                if let Some(t) = self.tetromino.as_mut() {
-                    t.pos.1 += 1;
-                }
+        if hard_drop {
+            self.hard_drop_tap_timer = 0.0;
+            let cells_dropped = self.drop_distance(&curr);
+            if let Some(t) = self.tetromino.as_mut() {
+                t.pos.1 += cells_dropped;
+            }
+            self.score += cells_dropped as u32 * HARD_DROP_POINTS_PER_CELL;
+            if cells_dropped > 0 {
+                self.last_move_rotation = false;
+            }
+            if let Some(t) = self.tetromino {
+                self.lock_flashes.push(LockFlash {
+                    cells: t.shape.map(|[dx, dy]| [t.pos.0 + dx, t.pos.1 + dy]),
+                    distance: cells_dropped,
+                    timer: LOCK_FLASH_DURATION,
+                });
             }
             self.lock_tetromino();
             return;
         }
 
-        // For other inputs, we can use a local copy.
-        let curr = self.tetromino.unwrap();
-        if is_key_pressed(KeyCode::Left) {
+        // For other inputs, we can use the local copy taken above.
+        if input.pressed(GameAction::MoveLeft) {
             if !self.check_collision(&curr.shape, (curr.pos.0 - 1, curr.pos.1)) {
                 self.move_tetromino((-1, 0));
-                self.left_timer = INITIAL_HORIZONTAL_DELAY;
-            }
-        } else if is_key_down(KeyCode::Left) {
-            self.left_timer -= delta;
-            if self.left_timer <= 0.0 {
-                if !self.check_collision(&curr.shape, (curr.pos.0 - 1, curr.pos.1)) {
-                    self.move_tetromino((-1, 0));
-                    self.left_timer = HORIZONTAL_REPEAT_DELAY;
-                }
+                self.reset_lock_delay();
+                self.left_timer = self.handling.das;
             }
+        } else if input.down(GameAction::MoveLeft) {
+            self.left_timer = self.auto_shift(-1, self.left_timer - delta);
         } else {
             self.left_timer = 0.0;
         }
 
-        if is_key_pressed(KeyCode::Right) {
+        if input.pressed(GameAction::MoveRight) {
             if !self.check_collision(&curr.shape, (curr.pos.0 + 1, curr.pos.1)) {
                 self.move_tetromino((1, 0));
-                self.right_timer = INITIAL_HORIZONTAL_DELAY;
-            }
-        } else if is_key_down(KeyCode::Right) {
-            self.right_timer -= delta;
-            if self.right_timer <= 0.0 {
-                if !self.check_collision(&curr.shape, (curr.pos.0 + 1, curr.pos.1)) {
-                    self.move_tetromino((1, 0));
-                    self.right_timer = HORIZONTAL_REPEAT_DELAY;
-                }
+                self.reset_lock_delay();
+                self.right_timer = self.handling.das;
             }
+        } else if input.down(GameAction::MoveRight) {
+            self.right_timer = self.auto_shift(1, self.right_timer - delta);
         } else {
             self.right_timer = 0.0;
         }
 
-        if is_key_pressed(KeyCode::Z) {
-            let new_shape = rotate_shape(&curr.shape, curr.t_type, false);
-            if !self.check_collision(&new_shape, curr.pos) {
-                self.set_tetromino_shape(new_shape);
-            }
+        if input.pressed(GameAction::RotateCcw) {
+            self.try_rotate(false);
         }
-        if is_key_pressed(KeyCode::X) {
-            let new_shape = rotate_shape(&curr.shape, curr.t_type, true);
-            if !self.check_collision(&new_shape, curr.pos) {
-                self.set_tetromino_shape(new_shape);
-            }
+        if input.pressed(GameAction::RotateCw) {
+            self.try_rotate(true);
         }
-
-        if is_key_down(KeyCode::Down) {
-            self.fall_timer = 0.0;
-            if !self.check_collision(&curr.shape, (curr.pos.0, curr.pos.1 + 1)) {
-                self.move_tetromino((0, 1));
-            }
+        if input.pressed(GameAction::Rotate180) {
+            self.try_rotate_180();
         }
 
-        if is_key_pressed(KeyCode::M) {
-            self.mus_mgr.mute();
+        // Sonic drop: straight to the floor without locking, scored like a soft drop.
+        if input.pressed(GameAction::SonicDrop) {
+            if let Some(t) = self.tetromino {
+                let cells_dropped = self.drop_distance(&t);
+                if cells_dropped > 0 {
+                    self.move_tetromino((0, cells_dropped));
+                    self.score += cells_dropped as u32 * SOFT_DROP_POINTS_PER_CELL;
+                }
+                // Landed: the lock delay starts fresh on the next step rather than mid-fall.
+                self.fall_timer = 0.0;
+            }
         }
 
-        if is_key_pressed(KeyCode::N) {
-            self.mus_mgr.play_song();
+        // Instant soft drop slides to the floor like a sonic drop; otherwise gravity speeds up in step.
+        if input.down(GameAction::SoftDrop) && self.handling.instant_soft_drop {
+            if let Some(t) = self.tetromino {
+                let cells_dropped = self.drop_distance(&t);
+                if cells_dropped > 0 {
+                    self.move_tetromino((0, cells_dropped));
+                    self.score += cells_dropped as u32 * SOFT_DROP_POINTS_PER_CELL;
+                }
+            }
+            self.fall_timer = 0.0;
         }
 
-        if is_key_pressed(KeyCode::C) && !self.hold_used {
-            self.hold_used = true;
+        if self.hold_enabled && input.pressed(GameAction::Hold) && !self.hold_used {
             let mut current_piece = curr;
             current_piece.shape = TETROMINO_SHAPES[current_piece.t_type as usize];
-            if let Some(mut hold_piece) = self.hold_tetromino.take() {
+            current_piece.rotation = 0;
+            if let Some(mut hold_piece) = self.hold_tetromino {
                 hold_piece.shape = TETROMINO_SHAPES[hold_piece.t_type as usize];
-                hold_piece.pos = (GRID_WIDTH as i32 / 2 - 2, 0);
-                if self.check_collision(&hold_piece.shape, hold_piece.pos) {
-                    self.hold_tetromino = Some(hold_piece);
-                } else {
+                hold_piece.rotation = 0;
+                hold_piece.pos = spawn_pos(hold_piece.t_type, self.width);
+                // A swap that would collide leaves everything untouched, hold included.
+                if !self.check_collision(&hold_piece.shape, hold_piece.pos) {
+                    self.hold_used = true;
                     self.hold_tetromino = Some(current_piece);
                     self.tetromino = Some(hold_piece);
+                    self.finesse_presses = 0;
+                    self.lock_delay_timer = 0.0;
+                    self.lock_delay_resets = 0;
+                    self.last_move_rotation = false;
                 }
             } else {
+                // First hold of the game: the piece that comes in is the front of next_queue,
+                // i.e. exactly what the Next preview showed, and the queue refills at the back.
                 self.hold_tetromino = Some(current_piece);
                 self.tetromino = None;
                 self.spawn_new_tetromino();
+                // Spawning clears hold_used, so mark it after the new piece is out.
+                self.hold_used = true;
             }
         }
     }
@@ -633,24 +2850,655 @@ impl GameState {
         if let Some(mut t) = self.tetromino {
             t.pos = (t.pos.0 + dx, t.pos.1 + dy);
             self.tetromino = Some(t);
+            self.last_move_rotation = false;
+        }
+    }
+
+    // Rotates the active piece, trying each SRS kick offset in order and
+    // applying the first one that fits.
+    pub fn try_rotate(&mut self, clockwise: bool) {
+        if let Some(t) = self.tetromino.and_then(|t| rotated(&self.board, &t, clockwise)) {
+            self.tetromino = Some(t);
+            self.reset_lock_delay();
+            self.last_move_rotation = true;
+        }
+    }
+
+    // Pause menu: Up/Down picks an option and Enter confirms it. The pause key
+    // also resumes when it isn't Enter.
+    fn update_pause_menu(&mut self) {
+        let len = PauseOption::ALL.len();
+        if is_key_pressed(KeyCode::Up) {
+            self.pause_selection = (self.pause_selection + len - 1) % len;
+        }
+        if is_key_pressed(KeyCode::Down) {
+            self.pause_selection = (self.pause_selection + 1) % len;
+        }
+        let choice = if is_key_pressed(KeyCode::Enter) {
+            PauseOption::ALL[self.pause_selection]
+        } else if is_key_pressed(self.config.key_bindings.pause) {
+            PauseOption::Resume
+        } else {
+            return;
+        };
+        // Every choice leaves the pause, so get the music going again first.
+        self.paused = false;
+        self.mus_mgr.pause();
+        match choice {
+            PauseOption::Resume => {}
+            PauseOption::Restart => self.restart(),
+            PauseOption::Quit => {
+                self.playback = None;
+                self.started = false;
+            }
+        }
+    }
+
+    // Starts the current mode over with the same settings, or replays the
+    // current replay from its first frame.
+    fn restart(&mut self) {
+        if let Some(playback) = self.playback.as_mut() {
+            playback.rewind();
+        }
+        self.start_game();
+    }
+
+    // Flips the active piece in one step, using the small KICKS_180 set.
+    pub fn try_rotate_180(&mut self) {
+        if let Some(t) = self.tetromino.and_then(|t| rotated_180(&self.board, &t)) {
+            self.tetromino = Some(t);
+            self.reset_lock_delay();
+            self.last_move_rotation = true;
+        }
+    }
+
+    // Start-screen navigation: Up/Down picks an option, Left/Right changes it.
+    fn update_menu(&mut self) {
+        // While renaming, the name owns the keyboard: no navigation or hotkeys.
+        if self.editing_name {
+            self.update_name_entry();
+            return;
+        }
+        if let Some(action) = self.rebinding {
+            self.update_rebinding(action);
+            return;
+        }
+        let options = self.menu_screen.options();
+        let selected = options[self.menu_selection];
+        let on_player = selected == MenuOption::Player && !self.show_leaderboard;
+        if on_player && is_key_pressed(KeyCode::Enter) {
+            self.editing_name = true;
+            return;
+        } else if on_player && is_key_pressed(KeyCode::Tab) {
+            self.add_profile();
+        } else if is_key_pressed(KeyCode::L) {
+            self.show_leaderboard = !self.show_leaderboard;
+        } else if is_key_pressed(KeyCode::P) {
+            match latest_replay_path().and_then(|path| ReplayInput::load(&path)) {
+                Some(playback) => self.start_replay(playback),
+                None => eprintln!("No replay to play in {}", REPLAY_DIR),
+            }
+            return;
+        }
+        if self.show_leaderboard {
+            return;
+        }
+        if is_key_pressed(KeyCode::Enter) {
+            match selected {
+                MenuOption::Settings => self.open_menu_screen(MenuScreen::Settings),
+                MenuOption::Back => self.open_menu_screen(MenuScreen::Main),
+                MenuOption::Key(action) => self.rebinding = Some(action),
+                MenuOption::SfxVolume => {
+                    self.mus_mgr.mute_sfx();
+                    self.config.sfx_muted = self.mus_mgr.sfx_muted;
+                    self.save_config();
+                }
+                _ => {}
+            }
+            return;
+        }
+        if self.menu_screen == MenuScreen::Settings && is_key_pressed(KeyCode::Escape) {
+            self.open_menu_screen(MenuScreen::Main);
+            return;
+        }
+        if selected == MenuOption::Seed {
+            self.update_seed_entry();
+        }
+        let len = options.len();
+        if is_key_pressed(KeyCode::Up) {
+            self.menu_selection = (self.menu_selection + len - 1) % len;
+        }
+        if is_key_pressed(KeyCode::Down) {
+            self.menu_selection = (self.menu_selection + 1) % len;
+        }
+        let step = if is_key_pressed(KeyCode::Left) {
+            -1
+        } else if is_key_pressed(KeyCode::Right) {
+            1
+        } else {
+            return;
+        };
+        match selected {
+            MenuOption::Player => self.switch_profile(step),
+            MenuOption::Mode => self.game_mode = self.game_mode.cycle(step),
+            MenuOption::Music => {
+                let choice = self.config.music_choice(self.game_mode).cycle(step, self.mus_mgr.tracks.len());
+                self.config.mode_music.insert(self.game_mode.name().to_string(), choice);
+                self.save_config();
+            }
+            MenuOption::Difficulty => self.difficulty = self.difficulty.cycle(step),
+            MenuOption::Board => self.board_size = self.board_size.cycle(step),
+            MenuOption::Settings | MenuOption::Key(_) | MenuOption::Back => {}
+            MenuOption::Volume => self.nudge_music_volume(step as f32 * MUSIC_VOLUME_STEP),
+            MenuOption::SfxVolume => self.nudge_sfx_volume(step as f32 * MUSIC_VOLUME_STEP),
+            MenuOption::Colors => {
+                self.config.color_scheme = self.config.color_scheme.cycle(step);
+                self.save_config();
+            }
+            MenuOption::Symbols => {
+                self.config.piece_symbols = !self.config.piece_symbols;
+                self.save_config();
+            }
+            MenuOption::Grid => {
+                self.config.grid_lines = !self.config.grid_lines;
+                self.save_config();
+            }
+            MenuOption::Hold => {
+                self.config.hold_enabled = !self.config.hold_enabled;
+                self.save_config();
+            }
+            MenuOption::Ghost => {
+                self.config.ghost_enabled = !self.config.ghost_enabled;
+                self.save_config();
+            }
+            MenuOption::GhostStyle => {
+                self.config.ghost_style = self.config.ghost_style.cycle(step);
+                self.save_config();
+            }
+            MenuOption::Landing => {
+                self.config.landing_info = !self.config.landing_info;
+                self.save_config();
+            }
+            MenuOption::Hints => {
+                self.config.square_hints = !self.config.square_hints;
+                self.save_config();
+            }
+            MenuOption::ClearEffect => {
+                self.config.clear_animation = self.config.clear_animation.cycle(step);
+                self.save_config();
+            }
+            MenuOption::ClearClock => {
+                self.config.clock_pauses_on_clear = !self.config.clock_pauses_on_clear;
+                self.save_config();
+            }
+            MenuOption::HardDrop => {
+                self.config.confirm_hard_drop = !self.config.confirm_hard_drop;
+                self.save_config();
+            }
+            MenuOption::Mirror => {
+                self.config.mirrored = !self.config.mirrored;
+                self.save_config();
+            }
+            // Steps through the presets; a custom size steps to the first or last one.
+            MenuOption::Window => {
+                let size = (self.config.window_width, self.config.window_height);
+                let len = WINDOW_PRESETS.len() as i32;
+                let i = match WINDOW_PRESETS.iter().position(|&p| p == size) {
+                    Some(i) => (i as i32 + step).rem_euclid(len),
+                    None if step > 0 => 0,
+                    None => len - 1,
+                };
+                let (w, h) = WINDOW_PRESETS[i as usize];
+                (self.config.window_width, self.config.window_height) = (w, h);
+                request_new_screen_size(w as f32, h as f32);
+                self.save_config();
+            }
+            // Left clears the seed, Right rolls a new one to share.
+            MenuOption::Seed => {
+                self.seed = if step > 0 { Some(::rand::thread_rng().gen()) } else { None };
+            }
+        }
+    }
+
+    // Typing letters, digits, and spaces or Backspace renames the active profile; Enter or
+    // Escape finishes, tidying the name.
+    fn update_name_entry(&mut self) {
+        let mut typed: Vec<char> =
+            std::iter::from_fn(get_char_pressed).filter(|c| c.is_ascii_alphanumeric() || *c == ' ').collect();
+        typed.reverse();
+        let mut changed = false;
+        for c in typed {
+            let name = &mut self.config.player_name;
+            // No leading or doubled spaces.
+            if c == ' ' && (name.is_empty() || name.ends_with(' ')) {
+                continue;
+            }
+            if name.len() < MAX_PLAYER_NAME_LEN {
+                name.push(c.to_ascii_uppercase());
+                changed = true;
+            }
+        }
+        if is_key_pressed(KeyCode::Backspace) && self.config.player_name.pop().is_some() {
+            changed = true;
+        }
+        if changed {
+            self.save_config();
+        }
+        if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::Escape) {
+            self.editing_name = false;
+            self.tidy_name();
+        }
+    }
+
+    // Cleans up the active player's name, saving only if that changed it.
+    fn tidy_name(&mut self) {
+        let tidy = tidy_player_name(&self.config.player_name);
+        if tidy != self.config.player_name {
+            self.config.player_name = tidy;
+            self.save_config();
+        }
+    }
+
+    // Switches the start screen between its option lists. Coming back from Settings
+    // lands on the Settings row again.
+    fn open_menu_screen(&mut self, screen: MenuScreen) {
+        self.menu_screen = screen;
+        self.menu_selection = match screen {
+            MenuScreen::Main => MENU_OPTIONS.iter().position(|&o| o == MenuOption::Settings).unwrap_or(0),
+            MenuScreen::Settings => 0,
+        };
+    }
+
+    // Binds the next key pressed to `action`; Escape cancels. A key already used by another
+    // action swaps over to it, and the pause and stats reset keys can't be taken.
+    fn update_rebinding(&mut self, action: GameAction) {
+        let Some(key) = get_last_key_pressed() else { return };
+        self.rebinding = None;
+        let bindings = &mut self.config.key_bindings;
+        if key == KeyCode::Escape
+            || key == bindings.pause
+            || key == bindings.reset_stats
+            || RESERVED_KEYS.contains(&key)
+            || !BINDABLE_KEYS.contains(&key)
+        {
+            return;
+        }
+        let old = bindings.key(action);
+        for other in GameAction::ALL {
+            if other != action && bindings.key(other) == key {
+                bindings.set(other, old);
+            }
+        }
+        bindings.set(action, key);
+        self.save_config();
+    }
+
+    // True while a title-screen field wants every key, so hotkeys like Space and R stay out.
+    fn capturing_keys(&self) -> bool {
+        self.editing_name || self.rebinding.is_some()
+    }
+
+    // Adds a fresh profile with a numbered default name and switches to it.
+    fn add_profile(&mut self) {
+        self.save_config();
+        let name = format!("{}{}", DEFAULT_PLAYER_NAME, self.profiles.list.len() + 1);
+        self.profiles.list.push(Config {
+            player_name: name,
+            ..Config::default()
+        });
+        let newest = self.profiles.list.len() as i32 - 1;
+        self.switch_profile(newest - self.profiles.active as i32);
+    }
+
+    // Makes the next or previous profile active and applies its audio and window settings.
+    fn switch_profile(&mut self, step: i32) {
+        self.save_config();
+        let len = self.profiles.list.len() as i32;
+        self.profiles.active = (self.profiles.active as i32 + step).rem_euclid(len) as usize;
+        self.config = self.profiles.list[self.profiles.active].clone();
+        self.mus_mgr.set_music_volume(self.config.music_volume);
+        self.mus_mgr.set_muted(self.config.muted);
+        self.mus_mgr.set_sfx_volume(self.config.sfx_volume);
+        self.mus_mgr.set_sfx_muted(self.config.sfx_muted);
+        set_fullscreen(self.config.fullscreen);
+        request_new_screen_size(self.config.window_width as f32, self.config.window_height as f32);
+        if self.persist {
+            save_json(PROFILES_PATH, &self.profiles);
+        }
+    }
+
+    // Copies the active profile back into the profile list and saves them all.
+    fn save_config(&mut self) {
+        self.profiles.list[self.profiles.active] = self.config.clone();
+        if self.persist {
+            save_json(PROFILES_PATH, &self.profiles);
+        }
+    }
+
+    // Typing digits, Backspace, or pasting with Ctrl+V edits the seed.
+    fn update_seed_entry(&mut self) {
+        // The queue pops newest first, so reverse it back into typing order.
+        let mut digits: String = std::iter::from_fn(get_char_pressed).filter(|c| c.is_ascii_digit()).collect();
+        digits = digits.chars().rev().collect();
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        if ctrl && is_key_pressed(KeyCode::V) {
+            if let Some(pasted) = miniquad::window::clipboard_get() {
+                self.seed = None;
+                digits = pasted.chars().filter(|c| c.is_ascii_digit()).collect();
+            }
+        }
+        for c in digits.chars() {
+            let digit = c.to_digit(10).unwrap() as u64;
+            let next = self.seed.unwrap_or(0).checked_mul(10).and_then(|s| s.checked_add(digit));
+            if next.is_some() {
+                self.seed = next;
+            }
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            self.seed = self.seed.and_then(|s| if s < 10 { None } else { Some(s / 10) });
+        }
+    }
+
+    // Changes the music volume, saves it, and briefly shows the volume bar.
+    fn nudge_music_volume(&mut self, step: f32) {
+        // Round to whole steps so repeated nudges land exactly on 0.0 and 1.0.
+        let volume = ((self.mus_mgr.music_volume + step) / MUSIC_VOLUME_STEP).round() * MUSIC_VOLUME_STEP;
+        self.mus_mgr.set_music_volume(volume);
+        self.config.music_volume = self.mus_mgr.music_volume;
+        self.save_config();
+        self.volume_display_timer = VOLUME_DISPLAY_DURATION;
+    }
+
+    // Changes the sound effect volume in the same whole steps as the music, and saves it.
+    fn nudge_sfx_volume(&mut self, step: f32) {
+        let volume = ((self.mus_mgr.sfx_volume + step) / MUSIC_VOLUME_STEP).round() * MUSIC_VOLUME_STEP;
+        self.mus_mgr.set_sfx_volume(volume);
+        self.config.sfx_volume = self.mus_mgr.sfx_volume;
+        self.save_config();
+    }
+
+    // Keys that do something right now on the title screen, for the line under the options.
+    fn menu_hint(&self) -> &'static str {
+        if self.editing_name {
+            return "Type a name  Backspace: delete  Enter/Esc: done";
+        }
+        if self.rebinding.is_some() {
+            return "Press the new key  Esc: cancel";
+        }
+        match self.menu_screen.options()[self.menu_selection] {
+            MenuOption::Player => "Enter: rename  Left/Right: switch player  Tab: new player",
+            MenuOption::Seed => "Type digits or Ctrl+V  Backspace: delete  L: leaderboard  P: last replay",
+            MenuOption::Settings => "Enter: open settings  L: leaderboard  P: last replay",
+            MenuOption::Key(_) => "Enter: change key  Esc: back",
+            MenuOption::SfxVolume => "Left/Right: change  Enter: mute/unmute  Esc: back",
+            MenuOption::Back => "Enter/Esc: back",
+            _ if self.menu_screen == MenuScreen::Settings => "Up/Down: choose  Left/Right: change  Esc: back",
+            _ => "Up/Down: choose  Left/Right: change  L: leaderboard  P: last replay",
+        }
+    }
+
+    fn menu_label(&self, option: MenuOption) -> String {
+        match option {
+            MenuOption::Player if self.editing_name => format!("Player: {}_", self.config.player_name),
+            MenuOption::Player => format!("Player: < {} >", self.config.player_name),
+            MenuOption::Mode => format!("Mode: < {} >", self.game_mode.name()),
+            MenuOption::Music => format!("Music: < {} >", self.config.music_choice(self.game_mode).name()),
+            MenuOption::Difficulty => format!("Difficulty: < {} >", self.difficulty.name()),
+            MenuOption::Board => format!("Board: < {} >", self.board_size.name()),
+            MenuOption::Settings => "Settings...".to_string(),
+            MenuOption::Volume => format!("Music Volume: < {}% >", (self.mus_mgr.music_volume * 100.0).round() as u32),
+            MenuOption::SfxVolume if self.mus_mgr.sfx_muted => "SFX Volume: < muted >".to_string(),
+            MenuOption::SfxVolume => format!("SFX Volume: < {}% >", (self.mus_mgr.sfx_volume * 100.0).round() as u32),
+            MenuOption::Key(action) if self.rebinding == Some(action) => format!("{}: press a key", action.name()),
+            MenuOption::Key(action) => format!("{}: {:?}", action.name(), self.config.key_bindings.key(action)),
+            MenuOption::Back => "Back".to_string(),
+            MenuOption::Colors => format!("Colors: < {} >", self.config.color_scheme.name()),
+            MenuOption::Symbols => {
+                format!("Piece Symbols: < {} >", if self.config.piece_symbols { "On" } else { "Off" })
+            }
+            MenuOption::Grid => {
+                format!("Grid Lines: < {} >", if self.config.grid_lines { "On" } else { "Off" })
+            }
+            MenuOption::Hold => {
+                format!("Hold: < {} >", if self.config.hold_enabled { "On" } else { "Off" })
+            }
+            MenuOption::Ghost => {
+                format!("Ghost Piece: < {} >", if self.config.ghost_enabled { "On" } else { "Off" })
+            }
+            MenuOption::GhostStyle => format!("Ghost Style: < {} >", self.config.ghost_style.name()),
+            MenuOption::Landing => {
+                format!("Landing Info: < {} >", if self.config.landing_info { "On" } else { "Off" })
+            }
+            MenuOption::Hints => {
+                format!("Square Hints: < {} >", if self.config.square_hints { "On" } else { "Off" })
+            }
+            MenuOption::ClearEffect => format!("Line Clear: < {} >", self.config.clear_animation.name()),
+            MenuOption::ClearClock => {
+                format!("Timer During Clears: < {} >", if self.config.clock_pauses_on_clear { "Stops" } else { "Runs" })
+            }
+            MenuOption::HardDrop => {
+                format!("Hard Drop: < {} >", if self.config.confirm_hard_drop { "Double Tap" } else { "Instant" })
+            }
+            MenuOption::Mirror => format!("Mirror: < {} >", if self.config.mirrored { "On" } else { "Off" }),
+            MenuOption::Window => {
+                let size = (self.config.window_width, self.config.window_height);
+                let custom = if WINDOW_PRESETS.contains(&size) { "" } else { " (custom)" };
+                format!("Window: < {}x{}{} >", size.0, size.1, custom)
+            }
+            MenuOption::Seed => match self.seed {
+                Some(seed) => format!("Seed: < {} >", seed),
+                None => "Seed: < Random >".to_string(),
+            },
+        }
+    }
+
+    // Pushes back a running lock delay after a successful move or rotation,
+    // up to MAX_LOCK_DELAY_RESETS times per piece.
+    fn reset_lock_delay(&mut self) {
+        if self.lock_delay_timer > 0.0 && self.lock_delay_resets < MAX_LOCK_DELAY_RESETS {
+            self.lock_delay_timer = self.difficulty.lock_delay();
+            self.lock_delay_resets += 1;
+        }
+    }
+
+    // Adds one row for the run that just ended to HISTORY_PATH, writing the header first
+    // if the file is new.
+    fn append_history(&self, timestamp: u64) {
+        const PIECES: [TetrominoType; 7] = [
+            TetrominoType::I,
+            TetrominoType::O,
+            TetrominoType::T,
+            TetrominoType::S,
+            TetrominoType::Z,
+            TetrominoType::J,
+            TetrominoType::L,
+        ];
+        let is_new = !Path::new(HISTORY_PATH).exists();
+        let mut file = match fs::OpenOptions::new().create(true).append(true).open(HISTORY_PATH) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Failed to open {}: {}", HISTORY_PATH, e);
+                return;
+            }
+        };
+        let mut text = String::new();
+        if is_new {
+            text.push_str("date,player,mode,difficulty,score,lines,seconds,I,O,T,S,Z,J,L\n");
+        }
+        let counts: Vec<String> = PIECES
+            .iter()
+            .map(|piece| self.piece_statistics.get(piece).unwrap_or(&0).to_string())
+            .collect();
+        text.push_str(&format!(
+            "{},{},{},{},{},{},{:.2},{}\n",
+            format_utc(timestamp),
+            self.config.player_name,
+            self.game_mode.name(),
+            self.difficulty.name(),
+            self.score,
+            self.lines_cleared,
+            self.play_time,
+            counts.join(","),
+        ));
+        if let Err(e) = file.write_all(text.as_bytes()) {
+            eprintln!("Failed to write {}: {}", HISTORY_PATH, e);
+        }
+    }
+
+    // Called when the window is asked to close. A run in progress asks for
+    // confirmation first; anything else can close right away.
+    pub fn request_quit(&mut self) {
+        if self.started && !self.game_over && self.playback.is_none() {
+            self.confirm_quit = true;
+        } else {
+            self.quit_confirmed = true;
+        }
+    }
+
+    // Y ends the run (saving any new record, leaderboard entry, and replay) and quits.
+    fn update_quit_confirm(&mut self) {
+        if is_key_pressed(KeyCode::Y) {
+            self.end_game(EndReason::Quit);
+            self.quit_confirmed = true;
+        } else if is_key_pressed(KeyCode::N) || is_key_pressed(KeyCode::Escape) {
+            self.confirm_quit = false;
+        }
+    }
+
+    pub fn update(&mut self) {
+        self.mus_mgr.update_audio(get_frame_time());
+        if self.confirm_quit {
+            self.update_quit_confirm();
+            return;
+        }
+        if is_key_pressed(KeyCode::F3) {
+            self.show_debug = !self.show_debug;
+        }
+        if is_key_pressed(KeyCode::F11) {
+            self.config.fullscreen = !self.config.fullscreen;
+            set_fullscreen(self.config.fullscreen);
+            self.save_config();
+        }
+        self.track_window_size();
+        if is_key_pressed(KeyCode::R) && !self.paused && !self.capturing_keys() && (self.started || self.game_over) {
+            self.restart();
+        }
+        if self.paused {
+            self.update_pause_menu();
+        } else if self.started
+            && !self.game_over
+            && (is_key_pressed(self.config.key_bindings.pause) || get_frame_time() > STALL_PAUSE_SECONDS)
+        {
+            self.paused = true;
+            self.pause_selection = 0;
+            self.mus_mgr.pause();
+        }
+        if !self.started {
+            self.update_menu();
+        }
+        // Typed characters only matter to the name and seed entry; don't let them pile up.
+        clear_input_queue();
+        if !self.started || self.game_over {
+            return;
+        }
+        if self.playback.is_some() && is_key_pressed(KeyCode::Escape) {
+            if self.paused {
+                self.paused = false;
+                self.mus_mgr.pause();
+            }
+            self.playback = None;
+            self.started = false;
+            return;
+        }
+        if is_key_pressed(KeyCode::M) {
+            self.mus_mgr.mute_music();
+            self.config.muted = self.mus_mgr.muted;
+            self.save_config();
+        }
+        if is_key_pressed(KeyCode::N) {
+            self.mus_mgr.next_song();
+        }
+        if is_key_pressed(KeyCode::H) {
+            self.config.show_controls = Some(!self.config.show_controls.unwrap_or(true));
+            self.save_config();
+        }
+        // Zeroing the piece counts is display-only, so it stays out of the replay.
+        self.stats_reset_timer = (self.stats_reset_timer - get_frame_time()).max(0.0);
+        if self.started && !self.game_over && is_key_pressed(self.config.key_bindings.reset_stats) {
+            self.piece_statistics.values_mut().for_each(|count| *count = 0);
+            self.stats_reset_timer = STATS_RESET_DISPLAY_DURATION;
+        }
+        self.volume_display_timer = (self.volume_display_timer - get_frame_time()).max(0.0);
+        let volume_step = if is_key_pressed(KeyCode::Equal) || is_key_pressed(KeyCode::KpAdd) {
+            MUSIC_VOLUME_STEP
+        } else if is_key_pressed(KeyCode::Minus) || is_key_pressed(KeyCode::KpSubtract) {
+            -MUSIC_VOLUME_STEP
+        } else {
+            0.0
+        };
+        if volume_step != 0.0 {
+            self.nudge_music_volume(volume_step);
         }
-    }
 
-    pub fn set_tetromino_shape(&mut self, shape: [[i32; 2]; 4]) {
-        if let Some(mut t) = self.tetromino {
-            t.shape = shape;
-            self.tetromino = Some(t);
+        self.keyboard.gather(&self.config.key_bindings);
+        let steps = if self.paused {
+            // A paused replay can be stepped through one frame at a time.
+            self.keyboard.clear_presses();
+            u32::from(self.playback.is_some() && is_key_pressed(KeyCode::Period))
+        } else {
+            self.sim_accumulator += get_frame_time();
+            let steps = (self.sim_accumulator / SIM_STEP) as u32;
+            self.sim_accumulator -= steps as f32 * SIM_STEP;
+            steps.min(MAX_SIM_STEPS_PER_FRAME)
+        };
+        for _ in 0..steps {
+            if !self.started || self.game_over {
+                break;
+            }
+            self.frame += 1;
+            let source: &mut dyn InputSource = match self.playback.as_mut() {
+                Some(playback) => playback,
+                None => &mut self.keyboard,
+            };
+            let input = source.poll(self.frame);
+            // Record every running frame, including the line-clear freeze, so
+            // releases made while input is ignored still reach the replay.
+            if self.playback.is_none() {
+                self.record_inputs(&input);
+            }
+            self.step(&input);
         }
     }
 
-    pub fn update(&mut self) {
-        let dt = get_frame_time();
-        if !self.game_over && is_key_pressed(KeyCode::Enter) {
-            self.paused = !self.paused;
-            self.mus_mgr.pause();
+    // Advances the game by one fixed simulation step.
+    fn step(&mut self, input: &InputFrame) {
+        let dt = SIM_STEP;
+        self.lock_flashes.retain_mut(|flash| {
+            flash.timer -= dt;
+            flash.timer > 0.0
+        });
+        self.t_spin_popup_timer = (self.t_spin_popup_timer - dt).max(0.0);
+        self.high_score_banner_timer = (self.high_score_banner_timer - dt).max(0.0);
+        self.check_high_score();
+        self.particles.retain_mut(|p| {
+            p.vy += PARTICLE_GRAVITY * dt;
+            p.x += p.vx * dt;
+            p.y += p.vy * dt;
+            p.life -= dt;
+            p.life > 0.0
+        });
+        // The countdown runs through the line-clear freeze unless the player chose otherwise.
+        let clock_stopped = self.clock_pauses_on_clear && self.line_clear_timer > 0.0;
+        if self.game_mode.has_countdown() && !clock_stopped {
+            self.time_remaining -= dt;
+            if self.time_remaining <= 0.0 {
+                self.time_remaining = 0.0;
+                self.end_game(EndReason::TimeUp);
+                return;
+            }
         }
-        if self.paused || !self.started || self.game_over {
-            return;
+        if self.game_mode.has_goal() {
+            self.race_time += dt;
         }
         if self.line_clear_timer > 0.0 {
             self.line_clear_timer -= dt;
@@ -659,48 +3507,227 @@ impl GameState {
             }
             return;
         }
-        self.process_input(dt);
+        self.play_time += dt;
+        if self.game_mode == GameMode::Survival {
+            self.garbage_timer -= dt;
+            if self.garbage_timer <= 0.0 {
+                self.garbage_timer += self.survival_rise_interval();
+                self.rise_garbage();
+                if self.game_over {
+                    return;
+                }
+            }
+        }
+        if self.game_mode == GameMode::Practice && input.pressed(GameAction::Undo) {
+            self.undo_lock();
+        }
+        // On a mirrored board, left and right follow the flipped picture.
+        let input = if self.mirrored { input.mirrored() } else { *input };
+        self.process_input(&input, dt);
         if let Some(curr) = self.tetromino {
-            let speed = if is_key_down(KeyCode::Down) { SOFT_DROP_SPEED } else { FALL_SPEED };
-            let fall_interval = 1.0 / speed;
-            self.fall_timer += dt;
-            if self.fall_timer >= fall_interval {
-                self.fall_timer -= fall_interval;
-                if self.check_collision(&curr.shape, (curr.pos.0, curr.pos.1 + 1)) {
+            if self.check_collision(&curr.shape, (curr.pos.0, curr.pos.1 + 1)) {
+                // Grounded: start the lock delay on touchdown and lock once it runs out. This
+                // holds however the piece landed, so soft-dropping into the floor never locks at once.
+                if self.lock_delay_timer <= 0.0 {
+                    self.lock_delay_timer = self.difficulty.lock_delay();
+                }
+                self.lock_delay_timer -= dt;
+                if self.lock_delay_timer <= 0.0 {
                     self.lock_tetromino();
-                } else {
-                    self.move_tetromino((0, 1));
+                }
+            } else {
+                self.lock_delay_timer = 0.0;
+                let mut fall_interval = self.fall_interval();
+                // Soft drop only counts (and scores) when it is faster than gravity.
+                let soft_interval = 1.0 / self.handling.soft_drop_speed;
+                let soft_dropping = input.down(GameAction::SoftDrop) && soft_interval < fall_interval;
+                if soft_dropping {
+                    fall_interval = soft_interval;
+                    // The first row drops on the press itself rather than one interval later.
+                    if input.pressed(GameAction::SoftDrop) {
+                        self.fall_timer = self.fall_timer.max(fall_interval - dt);
+                    }
+                }
+                // Fast gravity can fall several rows in one step.
+                self.fall_timer += dt;
+                while self.fall_timer >= fall_interval {
+                    self.fall_timer -= fall_interval;
+                    match self.tetromino {
+                        Some(t) if !self.check_collision(&t.shape, (t.pos.0, t.pos.1 + 1)) => {
+                            self.move_tetromino((0, 1));
+                            if soft_dropping {
+                                self.score += SOFT_DROP_POINTS_PER_CELL;
+                            }
+                        }
+                        _ => {
+                            self.fall_timer = 0.0;
+                            break;
+                        }
+                    }
                 }
             }
         }
         self.update_square_effects(dt);
     }
 
+    // Celebrates the first time this run's score passes the saved high score.
+    // Race modes are judged on time, and a first run has no record to beat.
+    fn check_high_score(&mut self) {
+        if self.high_score_beaten || self.game_mode.is_race() || !self.run_keeps_scores() {
+            return;
+        }
+        let best = self.config.record(self.game_mode, self.board_size).high_score;
+        if best > 0 && self.score > best {
+            self.high_score_beaten = true;
+            self.high_score_banner_timer = HIGH_SCORE_BANNER_DURATION;
+            self.mus_mgr.play_sfx(&HIGH_SCORE_JINGLE);
+        }
+    }
+
+    // The title-screen line summing up the last run, stressing what its mode is judged on.
+    fn end_message(&self) -> (String, Color) {
+        if self.playback.is_some() {
+            return ("Replay Finished".to_string(), GRAY);
+        }
+        match self.end_reason {
+            EndReason::GoalReached if self.game_mode.is_race() => {
+                (format!("{} Complete! - Time: {}", self.game_mode.name(), format_time(self.race_time)), GREEN)
+            }
+            EndReason::GoalReached => (
+                format!("{} Complete! - Score: {}  Time: {}", self.game_mode.name(), self.score, format_time(self.race_time)),
+                GREEN,
+            ),
+            EndReason::TimeUp => (format!("Time's Up - Score: {}  Lines: {}", self.score, self.lines_cleared), WHITE),
+            EndReason::TopOut | EndReason::Quit if self.game_mode.is_race() => (
+                format!("Game Over - Lines: {}  Time: {}", self.lines_cleared, format_time(self.race_time)),
+                RED,
+            ),
+            _ if self.game_mode == GameMode::Zen => (
+                format!("Zen Session - Lines: {}  Time: {}", self.lines_cleared, format_time(self.play_time)),
+                WHITE,
+            ),
+            EndReason::TopOut | EndReason::Quit if self.game_mode == GameMode::Survival => (
+                format!(
+                    "Game Over - Survived: {}  Score: {}  Lines: {}",
+                    format_time(self.play_time),
+                    self.score,
+                    self.lines_cleared
+                ),
+                RED,
+            ),
+            EndReason::TopOut | EndReason::Quit => {
+                (format!("Game Over - Score: {}  Lines: {}", self.score, self.lines_cleared), RED)
+            }
+        }
+    }
+
+    // Seconds per row of gravity. Endless also speeds up with play time, up to 20G.
+    fn fall_interval(&self) -> f32 {
+        let interval = gravity_interval(self.level) * self.difficulty.gravity_scale();
+        if self.game_mode == GameMode::Endless {
+            let speedup = 0.5f32.powf(self.play_time / ENDLESS_SPEEDUP_SECONDS);
+            (interval * speedup).max(SIM_STEP / MAX_GRAVITY_ROWS_PER_FRAME)
+        } else {
+            interval
+        }
+    }
+
     pub fn draw(&mut self) {
         clear_background(BLACK_COLOR);
 
         // If the game hasn't started, show "Press SPACE to start"
         if !self.started {
             self.mus_mgr.reset();
+            if self.show_leaderboard {
+                self.draw_leaderboard();
+                return;
+            }
             let msg = "Press SPACE to start";
             let measure = measure_text(msg, None, 40, 1.0);
             let x = (screen_width() - measure.width) / 2.0;
-            let y = (screen_height() - measure.height) / 2.0;
-            draw_text(msg, x, y, 40.0, YELLOW);
+            // Center the prompt, option rows, and hints together, with room above for the
+            // last run's result. Rows tighten up to fit short windows.
+            let options = self.menu_screen.options();
+            let row_h = ((screen_height() - 300.0) / options.len() as f32).clamp(22.0, 40.0);
+            let row_font = (row_h * 0.75).min(30.0);
+            let block_h = 50.0 + (options.len() - 1) as f32 * row_h + 130.0;
+            let top = (screen_height() - block_h) / 2.0 + 25.0;
+            draw_text(msg, x, top, 40.0, YELLOW);
+            if self.game_over {
+                let (over_msg, over_color) = self.end_message();
+                let measure = measure_text(&over_msg, None, 40, 1.0);
+                draw_text(&over_msg, (screen_width() - measure.width) / 2.0, top - 50.0, 40.0, over_color);
+            }
+            for (i, &option) in options.iter().enumerate() {
+                let label = self.menu_label(option);
+                let color = if i == self.menu_selection { YELLOW } else { WHITE };
+                let measure = measure_text(&label, None, row_font as u16, 1.0);
+                draw_text(&label, (screen_width() - measure.width) / 2.0, top + 50.0 + i as f32 * row_h, row_font, color);
+            }
+            let y = top + (options.len() - 1) as f32 * row_h;
+            let record = self.config.record(self.game_mode, self.board_size);
+            let best_msg = match (self.game_mode, record.best_time) {
+                (mode, Some(best)) if mode.is_race() => format!("Best: {}", format_time(best)),
+                (mode, None) if mode.is_race() => "Best: --".to_string(),
+                (GameMode::Zen, _) => "No scores, no game over - just stack".to_string(),
+                (GameMode::Marathon, Some(best)) => {
+                    format!("Best: {} ({} lines)  Fastest: {}", record.high_score, record.line_count, format_time(best))
+                }
+                _ => format!("Best: {} ({} lines)", record.high_score, record.line_count),
+            };
+            let measure = measure_text(&best_msg, None, 30, 1.0);
+            draw_text(&best_msg, (screen_width() - measure.width) / 2.0, y + 90.0, 30.0, GRAY);
+            let hint = self.menu_hint();
+            let measure = measure_text(hint, None, 24, 1.0);
+            draw_text(hint, (screen_width() - measure.width) / 2.0, y + 130.0, 24.0, GRAY);
             return;
         }
 
-        // Draw the main board background
-        let board_w = GRID_WIDTH as f32 * TILE_SIZE;
-        let board_h = GRID_HEIGHT as f32 * TILE_SIZE;
+        // Scale everything from the 1410x700 reference layout, keeping the board
+        // as tall as the window allows with room for the controls text below it.
+        let scale = (screen_height() / LAYOUT_BASE_HEIGHT)
+            .min(screen_width() / LAYOUT_MIN_WIDTH)
+            .max(MIN_LAYOUT_SCALE);
+        let tile = TILE_SIZE * scale * self.game_mode.cell_scale() as f32;
+        let board_w = self.width as f32 * tile;
+        let board_h = self.height as f32 * tile;
         let offset_x = (screen_width() - board_w) / 2.0;
-        let offset_y = (screen_height() - board_h) / 2.0 - 50.0;
+        let offset_y = (screen_height() - board_h - LAYOUT_BOTTOM_MARGIN * scale) / 2.0;
+        // Side panels hang off the board edges.
+        let left_x = offset_x - (PANEL_GAP + LEFT_PANEL_WIDTH) * scale;
+        let right_x = offset_x + board_w + PANEL_GAP * scale;
+        let font = |size: f32| size * scale;
+        // Left edge of `span` columns starting at column `x`, flipped when the board is mirrored.
+        let (cols, mirrored) = (self.width as f32, self.mirrored);
+        let col_x = |x: f32, span: f32| {
+            if mirrored { offset_x + (cols - x - span) * tile } else { offset_x + x * tile }
+        };
+
+        // Draw the main board background
         draw_rectangle(offset_x, offset_y, board_w, board_h, GAME_AREA_COLOR);
 
+        // Faint grid lines to help judge where a piece will land
+        if self.config.grid_lines {
+            for x in 1..self.width {
+                let lx = offset_x + x as f32 * tile;
+                draw_line(lx, offset_y, lx, offset_y + board_h, 1.0, GRID_LINE_COLOR);
+            }
+            for y in 1..self.height {
+                let ly = offset_y + y as f32 * tile;
+                draw_line(offset_x, ly, offset_x + board_w, ly, 1.0, GRID_LINE_COLOR);
+            }
+        }
+
         // Draw locked pieces on the board
-        for y in 0..GRID_HEIGHT {
-            for x in 0..GRID_WIDTH {
-                if let Some((color, _t, _id)) = self.board[y][x] {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if let Some((color, t_type, id)) = self.board[y][x] {
+                    // Invisible mode keeps the cell but stops drawing it once its delay passes.
+                    if self.game_mode == GameMode::Invisible
+                        && self.lock_times.get(&id).is_some_and(|&t| self.play_time - t > self.config.invisible_delay)
+                    {
+                        continue;
+                    }
                     let mut draw_color = color;
                     // If it's in an active 4x4 square effect, apply the blinking effect
                     for eff in &self.active_squares {
@@ -715,85 +3742,328 @@ impl GameState {
                             break;
                         }
                     }
-                    let px = offset_x + x as f32 * TILE_SIZE;
-                    let py = offset_y + y as f32 * TILE_SIZE;
-                    draw_snes_block(px, py, TILE_SIZE, draw_color);
+                    let px = col_x(x as f32, 1.0);
+                    let py = offset_y + y as f32 * tile;
+                    draw_snes_block(px, py, tile, draw_color);
+                    if self.config.piece_symbols {
+                        draw_piece_symbol(px, py, tile, t_type);
+                    }
+                }
+            }
+        }
+
+        // Hard-drop trails and lock flashes
+        for flash in &self.lock_flashes {
+            let fade = flash.timer / LOCK_FLASH_DURATION;
+            for &[x, y] in &flash.cells {
+                // Only the top cell of each column gets a streak above it.
+                if flash.cells.contains(&[x, y - 1]) {
+                    continue;
                 }
+                let px = col_x(x as f32, 1.0);
+                let trail_top = offset_y + (y - flash.distance) as f32 * tile;
+                let trail_h = flash.distance as f32 * tile;
+                draw_rectangle(px, trail_top, tile, trail_h, Color::new(1.0, 1.0, 1.0, 0.25 * fade));
+            }
+            for &[x, y] in &flash.cells {
+                let px = col_x(x as f32, 1.0);
+                let py = offset_y + y as f32 * tile;
+                draw_rectangle(px, py, tile, tile, Color::new(1.0, 1.0, 1.0, 0.8 * fade));
+            }
+        }
+
+        // Square hints: faintly outline regions that are close to a 4x4 bonus square.
+        // Invisible mode skips them, since they would give away the hidden blocks.
+        if self.config.square_hints && self.game_mode != GameMode::Invisible {
+            let hint_color = Color::new(GOLD_COLOR.r, GOLD_COLOR.g, GOLD_COLOR.b, 0.15);
+            for (x, y) in square_opportunities(&self.board, &self.squared_pieces) {
+                let px = col_x(x as f32, 4.0);
+                let py = offset_y + y as f32 * tile;
+                draw_rectangle(px, py, tile * 4.0, tile * 4.0, hint_color);
+                draw_rectangle_lines(px, py, tile * 4.0, tile * 4.0, 2.0, Color::new(GOLD_COLOR.r, GOLD_COLOR.g, GOLD_COLOR.b, 0.5));
             }
         }
 
         // Draw the "ghost" piece (projection)
         if let Some(curr) = self.tetromino {
-            let mut ghost = curr;
-            let mut iter = 0;
-            while !self.check_collision(&ghost.shape, (ghost.pos.0, ghost.pos.1 + 1)) && iter < 100 {
-                ghost.pos.1 += 1;
-                iter += 1;
-            }
-            let ghost_color = Color::new(curr.color.r, curr.color.g, curr.color.b, 0.3);
-            for &[dx, dy] in &ghost.shape {
-                let x = ghost.pos.0 + dx;
-                let y = ghost.pos.1 + dy;
-                let px = offset_x + x as f32 * TILE_SIZE;
-                let py = offset_y + y as f32 * TILE_SIZE;
-                draw_rectangle(px, py, TILE_SIZE, TILE_SIZE, ghost_color);
+            if self.ghost_enabled {
+                let mut ghost = curr;
+                ghost.pos.1 += self.drop_distance(&curr);
+                let opacity = self.config.ghost_opacity.clamp(0.0, 1.0);
+                let ghost_color = Color::new(curr.color.r, curr.color.g, curr.color.b, opacity);
+                for &[dx, dy] in &ghost.shape {
+                    let x = ghost.pos.0 + dx;
+                    let y = ghost.pos.1 + dy;
+                    let px = col_x(x as f32, 1.0);
+                    let py = offset_y + y as f32 * tile;
+                    match self.config.ghost_style {
+                        GhostStyle::Filled => draw_rectangle(px, py, tile, tile, ghost_color),
+                        GhostStyle::Outline => {
+                            let thickness = (2.0 * scale).max(1.0);
+                            draw_rectangle_lines(px, py, tile, tile, thickness, ghost_color);
+                        }
+                    }
+                }
             }
 
             // Draw the active falling piece
             for &[dx, dy] in &curr.shape {
                 let x = curr.pos.0 + dx;
                 let y = curr.pos.1 + dy;
-                let px = offset_x + x as f32 * TILE_SIZE;
-                let py = offset_y + y as f32 * TILE_SIZE;
-                draw_snes_block(px, py, TILE_SIZE, curr.color);
+                let px = col_x(x as f32, 1.0);
+                let py = offset_y + y as f32 * tile;
+                draw_snes_block(px, py, tile, curr.color);
+                if self.config.piece_symbols {
+                    draw_piece_symbol(px, py, tile, curr.t_type);
+                }
             }
         }
 
-        // If lines are clearing, flash them
-        draw_rectangle(offset_x, offset_y, board_w, TILE_SIZE * 2.0, BLACK_COLOR);
+        // If lines are clearing, animate them in the chosen style
+        draw_rectangle(offset_x, offset_y, board_w, tile * 2.0, BLACK_COLOR);
         if self.line_clear_timer > 0.0 {
-            let frames = (self.line_clear_timer * 60.0) as i32;
-            let flash_on = frames % 2 == 0;
-            let flash_color = if flash_on { WHITE } else { BLACK_COLOR };
+            let progress = 1.0 - self.line_clear_timer / LINE_CLEAR_DURATION;
             for &row in &self.clearing_lines {
-                let py = offset_y + row as f32 * TILE_SIZE;
-                draw_rectangle(offset_x, py, board_w, TILE_SIZE, flash_color);
+                let py = offset_y + row as f32 * tile;
+                match self.config.clear_animation {
+                    ClearAnimation::Flash => {
+                        let frames = (self.line_clear_timer * 60.0) as i32;
+                        let flash_color = if frames % 2 == 0 { WHITE } else { BLACK_COLOR };
+                        draw_rectangle(offset_x, py, board_w, tile, flash_color);
+                    }
+                    ClearAnimation::Wipe => {
+                        let w = board_w * progress;
+                        draw_rectangle(offset_x + (board_w - w) / 2.0, py, w, tile, GAME_AREA_COLOR);
+                    }
+                    ClearAnimation::Collapse => {
+                        let h = tile * (1.0 - progress);
+                        draw_rectangle(offset_x, py, board_w, tile, GAME_AREA_COLOR);
+                        draw_rectangle(offset_x, py + (tile - h) / 2.0, board_w, h, WHITE);
+                    }
+                }
+            }
+        }
+
+        // Landing info, printed in the hidden rows above the board: the visible columns
+        // the piece covers (1 on the left) and the row its bottom lands on (1 at the floor)
+        if let Some(curr) = self.tetromino.filter(|_| self.config.landing_info) {
+            let (mut left, mut right) = (i32::MAX, i32::MIN);
+            let mut bottom = 0;
+            for &[dx, dy] in &curr.shape {
+                let x = if self.mirrored { self.width as i32 - 1 - (curr.pos.0 + dx) } else { curr.pos.0 + dx };
+                left = left.min(x);
+                right = right.max(x);
+                bottom = bottom.max(curr.pos.1 + dy);
+            }
+            let row = self.height as i32 - (bottom + self.drop_distance(&curr));
+            let cols = if left == right { format!("Col {}", left + 1) } else { format!("Cols {}-{}", left + 1, right + 1) };
+            let info = format!("{}  Row {}", cols, row);
+            let size = font(24.0);
+            let measure = measure_text(&info, None, size as u16, 1.0);
+            draw_text(&info, offset_x + (board_w - measure.width) / 2.0, offset_y + tile + measure.height / 2.0, size, LIGHTGRAY);
+        }
+
+        // Lines and Score on the right side, pushed down below the next queue
+        let text_x = right_x + 8.0 * scale;
+        let hud_y = offset_y + (170.0 + self.next_queue.len().saturating_sub(1) as f32 * QUEUE_SPACING) * scale;
+        draw_text(&format!("Lines: {}", self.lines_cleared), text_x, hud_y, font(40.0), WHITE);
+        draw_text(&format!("Score: {}", self.score), text_x, hud_y + 50.0 * scale, font(40.0), WHITE);
+        draw_text(&format!("Level: {}", self.level), text_x, hud_y + 100.0 * scale, font(40.0), WHITE);
+        let played = self.play_time as u32;
+        draw_text(&format!("Played: {:02}:{:02}", played / 60, played % 60), text_x, hud_y + 150.0 * scale, font(40.0), WHITE);
+
+        if self.combo > 0 {
+            draw_text(&format!("Combo: {}", self.combo), text_x, hud_y + 250.0 * scale, font(40.0), ORANGE);
+        }
+        if self.back_to_back {
+            draw_text("B2B", text_x + 180.0 * scale, hud_y + 250.0 * scale, font(40.0), GOLD_COLOR);
+        }
+
+        // Pieces since the last I, red once the drought runs long
+        let drought = *self.pieces_since.get(&TetrominoType::I).unwrap_or(&0);
+        let drought_color = if drought > I_DROUGHT_WARNING { RED } else { WHITE };
+        draw_text(&format!("I Drought: {}", drought), text_x, hud_y + 300.0 * scale, font(40.0), drought_color);
+
+        // Seeded runs show their seed so attempts can be compared
+        if let Some(seed) = self.seed {
+            draw_text(&format!("Seed: {}", seed), text_x, hud_y + 340.0 * scale, font(30.0), GRAY);
+        }
+
+        // Countdown for Timed and Ultra modes
+        if self.game_mode.has_countdown() {
+            let secs = self.time_remaining.ceil() as u32;
+            let time_color = if secs <= 10 { RED } else { YELLOW };
+            draw_text(&format!("Time: {}:{:02}", secs / 60, secs % 60), text_x, hud_y + 200.0 * scale, font(40.0), time_color);
+        }
+
+        // Endless speed tier: one step per doubling of the time-based speedup
+        if self.game_mode == GameMode::Endless {
+            let tier = if self.fall_interval() <= SIM_STEP / MAX_GRAVITY_ROWS_PER_FRAME {
+                "Speed: 20G".to_string()
+            } else {
+                format!("Speed: {}", (self.play_time / ENDLESS_SPEEDUP_SECONDS) as u32 + 1)
+            };
+            draw_text(&tier, text_x, hud_y + 200.0 * scale, font(40.0), YELLOW);
+        }
+
+        // Survival: time until the next garbage row pushes up
+        if self.game_mode == GameMode::Survival {
+            let rise_color = if self.garbage_timer <= 1.0 { RED } else { YELLOW };
+            draw_text(&format!("Rise in: {:.1}", self.garbage_timer.max(0.0)), text_x, hud_y + 200.0 * scale, font(40.0), rise_color);
+        }
+
+        // Practice: how many pieces took more presses than they needed
+        if self.game_mode == GameMode::Practice {
+            draw_text(&format!("Finesse: {}", self.finesse_errors), text_x, hud_y + 200.0 * scale, font(40.0), YELLOW);
+        }
+
+        // Ultra is all about score, so show it big under the board
+        if self.game_mode == GameMode::Ultra {
+            let msg = format!("{}", self.score);
+            let measure = measure_text(&msg, None, font(50.0) as u16, 1.0);
+            draw_text(&msg, offset_x + (board_w - measure.width) / 2.0, offset_y + board_h + 50.0 * scale, font(50.0), YELLOW);
+        }
+
+        // Running clock for modes with a goal
+        if self.game_mode.has_goal() {
+            draw_text(&format_time(self.race_time), text_x, hud_y + 200.0 * scale, font(40.0), YELLOW);
+        }
+
+        // Danger: a pulsing red vignette around the board once the stack is high
+        if self.config.danger_effect && !self.game_over && self.stack_height() >= self.config.danger_height {
+            let strength = 0.35 + 0.15 * (self.play_time * DANGER_PULSE_SPEED).sin();
+            let band = tile / 4.0;
+            // The top two rows are hidden behind the spawn mask, so frame only the visible well.
+            let (top, well_h) = (offset_y + tile * 2.0, board_h - tile * 2.0);
+            for i in 0..6 {
+                let inset = i as f32 * band;
+                let alpha = strength * (1.0 - i as f32 / 6.0);
+                draw_rectangle_lines(
+                    offset_x + inset,
+                    top + inset,
+                    board_w - 2.0 * inset,
+                    well_h - 2.0 * inset,
+                    band,
+                    Color::new(1.0, 0.0, 0.0, alpha),
+                );
             }
         }
 
-        // Lines and Score on the right side
-        draw_text(&format!("Lines: {}", self.lines_cleared), screen_width() - 210.0, 170.0, 40.0, WHITE);
-        draw_text(&format!("Score: {}", self.score), screen_width() - 210.0, 220.0, 40.0, WHITE);
+        // Points earned by each new 4x4 square, floating up and fading above it
+        for eff in &self.active_squares {
+            if eff.age >= SQUARE_POPUP_DURATION {
+                continue;
+            }
+            let progress = eff.age / SQUARE_POPUP_DURATION;
+            let msg = format!("+{}", eff.points);
+            let measure = measure_text(&msg, None, font(36.0) as u16, 1.0);
+            let x = col_x(eff.x as f32, 4.0) + 2.0 * tile - measure.width / 2.0;
+            let y = offset_y + (eff.y as f32 - progress * SQUARE_POPUP_RISE) * tile;
+            let base = if eff.is_gold { GOLD_COLOR } else { SILVER_COLOR };
+            draw_text(&msg, x, y, font(36.0), Color::new(base.r, base.g, base.b, 1.0 - progress));
+        }
+
+        // Line-clear particles, fading as they run out of life
+        for p in &self.particles {
+            let size = tile * 0.25;
+            let alpha = (p.life / PARTICLE_LIFETIME).min(1.0);
+            let color = Color::new(p.color.r, p.color.g, p.color.b, alpha);
+            draw_rectangle(col_x(p.x, 0.0) - size / 2.0, offset_y + p.y * tile - size / 2.0, size, size, color);
+        }
+
+        // Volume bar, shown briefly after + or - changes the music volume
+        if self.volume_display_timer > 0.0 {
+            let bar_w = board_w * 0.8;
+            let bar_h = 16.0 * scale;
+            let bar_x = offset_x + (board_w - bar_w) / 2.0;
+            let bar_y = offset_y + board_h - 40.0 * scale;
+            let label = if self.mus_mgr.muted {
+                format!("Volume: {:.0}% (muted)", self.mus_mgr.music_volume * 100.0)
+            } else {
+                format!("Volume: {:.0}%", self.mus_mgr.music_volume * 100.0)
+            };
+            draw_text(&label, bar_x, bar_y - 8.0 * scale, font(24.0), WHITE);
+            draw_rectangle(bar_x, bar_y, bar_w, bar_h, BLACK_COLOR);
+            draw_rectangle(bar_x, bar_y, bar_w * self.mus_mgr.music_volume, bar_h, GREEN);
+            draw_rectangle_lines(bar_x, bar_y, bar_w, bar_h, 2.0, WHITE);
+        }
+
+        // T-spin popup, fading out over the top of the board
+        if self.t_spin_popup_timer > 0.0 {
+            let msg = "T-SPIN";
+            let alpha = self.t_spin_popup_timer / T_SPIN_POPUP_DURATION;
+            let measure = measure_text(msg, None, font(50.0) as u16, 1.0);
+            let x = offset_x + (board_w - measure.width) / 2.0;
+            draw_text(msg, x, offset_y + board_h / 3.0, font(50.0), Color::new(0.8, 0.3, 1.0, alpha));
+        }
+
+        // New high score banner, below the T-spin popup
+        if self.high_score_banner_timer > 0.0 {
+            let msg = "NEW HIGH SCORE!";
+            let alpha = (self.high_score_banner_timer / HIGH_SCORE_BANNER_DURATION * 2.0).min(1.0);
+            let measure = measure_text(msg, None, font(40.0) as u16, 1.0);
+            let x = offset_x + (board_w - measure.width) / 2.0;
+            draw_text(msg, x, offset_y + board_h / 2.0, font(40.0), Color::new(GOLD_COLOR.r, GOLD_COLOR.g, GOLD_COLOR.b, alpha));
+        }
 
-        // Game Over message
+        // Ending message: red for a top-out, neutral when time ran out, green for a win
         if self.game_over {
-            let msg = "Game Over";
-            let measure = measure_text(msg, None, 50, 1.0);
+            let (msg, color) = match self.end_reason {
+                EndReason::GoalReached => ("Complete!", GREEN),
+                EndReason::TimeUp => ("Time's Up", WHITE),
+                EndReason::TopOut | EndReason::Quit => ("Game Over", RED),
+            };
+            let measure = measure_text(msg, None, font(50.0) as u16, 1.0);
             let x = offset_x + (board_w - measure.width) / 2.0;
             let y = offset_y + board_h / 2.0;
-            draw_text(msg, x, y, 50.0, RED);
+            draw_text(msg, x, y, font(50.0), color);
         }
 
         // Pause overlay
         if self.paused {
             draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0,0.0,0.0,0.6));
             let msg = "Paused";
-            let measure = measure_text(msg, None, 50, 1.0);
-            draw_text(msg, (screen_width()-measure.width)/2.0, screen_height()/2.0, 50.0, YELLOW);
+            let measure = measure_text(msg, None, font(50.0) as u16, 1.0);
+            let menu_y = screen_height() / 2.0 - 60.0 * scale;
+            draw_text(msg, (screen_width()-measure.width)/2.0, menu_y, font(50.0), YELLOW);
+            for (i, option) in PauseOption::ALL.iter().enumerate() {
+                let color = if i == self.pause_selection { YELLOW } else { WHITE };
+                let measure = measure_text(option.name(), None, font(36.0) as u16, 1.0);
+                let y = menu_y + (60.0 + i as f32 * 45.0) * scale;
+                draw_text(option.name(), (screen_width() - measure.width) / 2.0, y, font(36.0), color);
+            }
+        }
+
+        // Replay banner over the top of the board
+        if self.playback.is_some() {
+            let msg = "REPLAY";
+            let measure = measure_text(msg, None, font(40.0) as u16, 1.0);
+            draw_text(msg, offset_x + (board_w - measure.width) / 2.0, offset_y - 15.0 * scale, font(40.0), RED);
+            let hint = if self.paused { ". step  Esc quit" } else { "Esc quit" };
+            let measure = measure_text(hint, None, font(24.0) as u16, 1.0);
+            draw_text(hint, offset_x + (board_w - measure.width) / 2.0, offset_y + board_h + 30.0 * scale, font(24.0), GRAY);
         }
 
         // -- LEFT SIDE PANELS: Hold piece & Piece Stats --
 
-        // Draw "Hold" text and hold piece preview
-        draw_text("Hold", 79.0, 55.0, 40.0, WHITE);
-        if let Some(ref hold_piece) = self.hold_tetromino {
-            draw_preview(hold_piece, 79.0, 90.0, PREVIEW_TILE_SIZE);
+        // Draw "Hold" text and hold piece preview, unless hold is switched off.
+        // Both dim once hold has been used for the current piece.
+        if self.hold_enabled {
+            let label_color = if self.hold_used { GRAY } else { WHITE };
+            draw_text("Hold", left_x, offset_y + 55.0 * scale, font(40.0), label_color);
+            if let Some(mut hold_piece) = self.hold_tetromino {
+                if self.hold_used {
+                    let c = hold_piece.color;
+                    hold_piece.color = Color::new(c.r * 0.4, c.g * 0.4, c.b * 0.4, 1.0);
+                }
+                draw_preview(&hold_piece, left_x, offset_y + 90.0 * scale, PREVIEW_TILE_SIZE * scale, PREVIEW_BOX_SIZE * scale, self.mirrored);
+            }
         }
 
         // Draw the piece statistics under the hold piece
-        let stats_label_x = 79.0;
-        let stats_label_y = 200.0;
-        draw_text("Piece Stats", stats_label_x, stats_label_y, 30.0, WHITE);
+        let stats_label_x = left_x;
+        let stats_label_y = offset_y + 200.0 * scale;
+        draw_text("Piece Stats", stats_label_x, stats_label_y, font(30.0), WHITE);
 
         let stat_types = [
             TetrominoType::I,
@@ -805,66 +4075,179 @@ impl GameState {
             TetrominoType::L,
         ];
 
-        // Each piece gets a small preview plus its count
+        let pieces: u32 = self.piece_statistics.values().sum();
+
+        // Each piece gets a small preview plus its count and share of all pieces
         for (i, &piece_type) in stat_types.iter().enumerate() {
-            let piece_y = stats_label_y + 40.0 + (i as f32 * 50.0);
+            let piece_y = stats_label_y + (40.0 + i as f32 * 50.0) * scale;
             // Create a dummy tetromino just for drawing its shape
             let t = Tetromino {
                 shape: TETROMINO_SHAPES[piece_type as usize],
                 pos: (0, 0),
-                color: NES_COLORS[piece_type as usize],
+                color: piece_color(piece_type, self.config.color_scheme),
                 t_type: piece_type,
+                rotation: 0,
             };
             // Draw a small preview on the left
-            draw_preview(&t, stats_label_x, piece_y, 15.0);
+            draw_preview(&t, stats_label_x, piece_y, 15.0 * scale, PREVIEW_BOX_SIZE * scale, self.mirrored);
             // Show the count on the right
-            let count = self.piece_statistics.get(&piece_type).unwrap_or(&0);
+            let count = *self.piece_statistics.get(&piece_type).unwrap_or(&0);
+            let percent = if pieces > 0 { count as f32 * 100.0 / pieces as f32 } else { 0.0 };
             draw_text(
-                &format!("{}", count),
-                stats_label_x + 50.0,
-                piece_y + 20.0,
-                20.0,
+                &format!("{} ({:.0}%)", count, percent),
+                stats_label_x + 50.0 * scale,
+                piece_y + 20.0 * scale,
+                font(20.0),
                 WHITE,
             );
         }
+        draw_text(&format!("Total: {}", pieces), stats_label_x, stats_label_y + 390.0 * scale, font(30.0), WHITE);
+
+        // Pace: pieces per second and lines per minute over the play clock
+        let (pps, lpm) = if self.play_time > 0.0 {
+            (pieces as f32 / self.play_time, self.lines_cleared as f32 * 60.0 / self.play_time)
+        } else {
+            (0.0, 0.0)
+        };
+        draw_text(&format!("PPS: {:.2}", pps), stats_label_x, stats_label_y + 420.0 * scale, font(30.0), WHITE);
+        draw_text(&format!("LPM: {:.1}", lpm), stats_label_x, stats_label_y + 450.0 * scale, font(30.0), WHITE);
+        if self.stats_reset_timer > 0.0 {
+            let alpha = (self.stats_reset_timer / STATS_RESET_DISPLAY_DURATION).min(1.0);
+            let color = Color::new(YELLOW.r, YELLOW.g, YELLOW.b, alpha);
+            draw_text("Stats reset", stats_label_x, stats_label_y + 480.0 * scale, font(24.0), color);
+        }
+
+        // -- RIGHT SIDE: Next piece label & queue previews --
+        draw_text("Next", text_x, offset_y + 55.0 * scale, font(40.0), WHITE);
+        for (i, next_piece) in self.next_queue.iter().enumerate() {
+            if i == 0 {
+                draw_preview(next_piece, right_x, offset_y + 70.0 * scale, PREVIEW_TILE_SIZE * scale, PREVIEW_BOX_SIZE * scale, self.mirrored);
+            } else {
+                let queue_y = offset_y + (85.0 + i as f32 * QUEUE_SPACING) * scale;
+                draw_preview(next_piece, right_x, queue_y, QUEUE_TILE_SIZE * scale, PREVIEW_BOX_SIZE * scale, self.mirrored);
+            }
+        }
 
-        // -- RIGHT SIDE: Next piece label & preview --
-        draw_text("Next", screen_width() - 210.0, 55.0, 40.0, WHITE);
-        if let Some(ref next_piece) = self.next_tetromino {
-            draw_preview(next_piece, screen_width() - 218.0, 70.0, PREVIEW_TILE_SIZE);
-        }
-
-        // Controls text at the bottom
-        let controls_text = "\
-Controls:
- Left/Right: Move
- Up: Hard Drop
- Down: Soft Drop
- Z/X: Rotate
- C: Hold
- Enter: Pause
- Space: Start
- N: Change Song
- M: Mute Music";
-        let text_x = 20.0;
-        let text_y = offset_y + board_h + 80.0;
-        let wrapped = wrap_text(controls_text, screen_width() - 40.0, 24);
+        // Controls text at the bottom, or just how to bring it back once hidden with H
+        let keys = self.config.key_bindings;
+        let controls_text = if self.config.show_controls.unwrap_or(true) {
+            let undo = if self.game_mode == GameMode::Practice { format!(" {:?}: Undo", keys.undo) } else { String::new() };
+            format!(
+                "Controls: {:?}/{:?}: Move {:?}: Hard Drop {:?}: Soft Drop {:?}: Sonic Drop {:?}/{:?}: Rotate \
+                 {:?}: Rotate 180 {:?}: Hold{} {:?}: Pause N: Change Song M: Mute Music H: Hide Controls",
+                keys.move_left, keys.move_right, keys.hard_drop, keys.soft_drop, keys.sonic_drop,
+                keys.rotate_ccw, keys.rotate_cw, keys.rotate_180, keys.hold, undo, keys.pause,
+            )
+        } else {
+            "H: Show Controls".to_string()
+        };
+        let controls_font = font(24.0) as u16;
+        let text_x = 20.0 * scale;
+        let text_y = offset_y + board_h + 80.0 * scale;
+        let wrapped = wrap_text(&controls_text, screen_width() - 2.0 * text_x, controls_font);
         draw_text_ex(
             &wrapped,
             text_x,
             text_y,
             TextParams {
                 font: None,
-                font_size: 24,
+                font_size: controls_font,
                 font_scale: 1.0,
                 font_scale_aspect: 1.0,
                 rotation: 0.0,
                 color: WHITE,
             },
         );
+
+        // Confirmation when the window is closed mid-game
+        if self.confirm_quit {
+            draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, 0.7));
+            let msg = "Quit and save this run?";
+            let measure = measure_text(msg, None, font(50.0) as u16, 1.0);
+            draw_text(msg, (screen_width() - measure.width) / 2.0, screen_height() / 2.0, font(50.0), YELLOW);
+            let hint = "Y: save and quit   N: keep playing";
+            let measure = measure_text(hint, None, font(30.0) as u16, 1.0);
+            draw_text(hint, (screen_width() - measure.width) / 2.0, screen_height() / 2.0 + 50.0 * scale, font(30.0), WHITE);
+        }
+    }
+
+    // Saves a manual window resize once the size has held for WINDOW_SAVE_DELAY,
+    // so dragging a window edge doesn't rewrite the profile every frame.
+    fn track_window_size(&mut self) {
+        let size = (screen_width().round() as u32, screen_height().round() as u32);
+        if self.config.fullscreen || size == (self.config.window_width, self.config.window_height) {
+            self.resize_timer = 0.0;
+            return;
+        }
+        self.resize_timer += get_frame_time();
+        if self.resize_timer >= WINDOW_SAVE_DELAY {
+            (self.config.window_width, self.config.window_height) = size;
+            self.save_config();
+            self.resize_timer = 0.0;
+        }
+    }
+
+    // Frame rate, frame time, and effect counts in the top-left corner, for chasing stutters.
+    fn draw_debug_overlay(&self) {
+        let lines = [
+            format!("FPS: {}", get_fps()),
+            format!("Frame: {:.2} ms", get_frame_time() * 1000.0),
+            format!("Particles: {}", self.particles.len()),
+            format!("Squares: {}", self.active_squares.len()),
+        ];
+        draw_rectangle(0.0, 0.0, 190.0, 10.0 + lines.len() as f32 * 22.0, Color::new(0.0, 0.0, 0.0, 0.7));
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(line, 8.0, 24.0 + i as f32 * 22.0, 22.0, GREEN);
+        }
+    }
+
+    fn draw_leaderboard(&self) {
+        let title = "Leaderboard";
+        let measure = measure_text(title, None, 50, 1.0);
+        draw_text(title, (screen_width() - measure.width) / 2.0, 80.0, 50.0, YELLOW);
+
+        let col_x = (screen_width() - 640.0) / 2.0;
+        draw_text("#   Name          Score     Lines   Mode", col_x, 140.0, 30.0, GRAY);
+        if self.leaderboard.is_empty() {
+            draw_text("No games played yet.", col_x, 190.0, 30.0, WHITE);
+        }
+        for (i, entry) in self.leaderboard.iter().enumerate() {
+            let row_y = 190.0 + i as f32 * 40.0;
+            draw_text(&format!("{}", i + 1), col_x, row_y, 30.0, WHITE);
+            draw_text(&entry.name, col_x + 50.0, row_y, 30.0, WHITE);
+            draw_text(&format!("{}", entry.score), col_x + 250.0, row_y, 30.0, WHITE);
+            draw_text(&format!("{}", entry.lines), col_x + 400.0, row_y, 30.0, WHITE);
+            draw_text(&entry.mode, col_x + 510.0, row_y, 30.0, WHITE);
+        }
+
+        let hint = "Press L to go back";
+        let measure = measure_text(hint, None, 24, 1.0);
+        draw_text(hint, (screen_width() - measure.width) / 2.0, screen_height() - 40.0, 24.0, GRAY);
     }
 }
 
+// Formats seconds as M:SS.mmm.
+fn format_time(secs: f32) -> String {
+    let millis = (secs * 1000.0) as u32;
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
+
+// Formats Unix seconds as a UTC "YYYY-MM-DD HH:MM:SS" date.
+fn format_utc(secs: u64) -> String {
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's days_from_civil, inverted).
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let time = secs % 86_400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
 fn wrap_text(text: &str, max_width: f32, font_size: u16) -> String {
     let mut result = String::new();
     let words: Vec<&str> = text.split_whitespace().collect();
@@ -916,7 +4299,35 @@ fn draw_snes_block(x: f32, y: f32, size: f32, color: Color) {
     draw_rectangle(x + size - border, y, border, size, shadow);
 }
 
-fn draw_preview(tetromino: &Tetromino, pos_x: f32, pos_y: f32, tile_size: f32) {
+// Draws the piece's letter centered on a block, for telling pieces apart without color.
+fn draw_piece_symbol(x: f32, y: f32, size: f32, t_type: TetrominoType) {
+    let symbol = piece_symbol(t_type);
+    let font_size = (size * 0.7) as u16;
+    let measure = measure_text(symbol, None, font_size, 1.0);
+    let tx = x + (size - measure.width) / 2.0;
+    let ty = y + (size + measure.height) / 2.0;
+    draw_text(symbol, tx, ty, font_size as f32, BLACK_COLOR);
+}
+
+// Grabs the frame just drawn and writes it to SCREENSHOT_DIR as a timestamped
+// PNG. Encoding happens on a background thread so the game doesn't hitch.
+fn save_screenshot() {
+    if let Err(e) = fs::create_dir_all(SCREENSHOT_DIR) {
+        eprintln!("Failed to create {}: {}", SCREENSHOT_DIR, e);
+        return;
+    }
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let path = format!("{}/tetris-{}.png", SCREENSHOT_DIR, millis);
+    let image = get_screen_data();
+    std::thread::spawn(move || image.export_png(&path));
+}
+
+// Draws a piece centered in a box_size x box_size square at (pos_x, pos_y), flipped
+// left to right when `mirrored` so it matches a mirrored board.
+fn draw_preview(tetromino: &Tetromino, pos_x: f32, pos_y: f32, tile_size: f32, box_size: f32, mirrored: bool) {
     let mut min_x = i32::MAX;
     let mut min_y = i32::MAX;
     let mut max_x = i32::MIN;
@@ -929,10 +4340,11 @@ fn draw_preview(tetromino: &Tetromino, pos_x: f32, pos_y: f32, tile_size: f32) {
     }
     let shape_w = (max_x - min_x + 1) as f32 * tile_size;
     let shape_h = (max_y - min_y + 1) as f32 * tile_size;
-    let offset_x = pos_x + (50.0 - shape_w) / 2.0;
-    let offset_y = pos_y + (50.0 - shape_h) / 2.0;
+    let offset_x = pos_x + (box_size - shape_w) / 2.0;
+    let offset_y = pos_y + (box_size - shape_h) / 2.0;
     for &[bx, by] in tetromino.shape.iter() {
-        let draw_x = offset_x + (bx - min_x) as f32 * tile_size;
+        let col = if mirrored { max_x - bx } else { bx - min_x };
+        let draw_x = offset_x + col as f32 * tile_size;
         let draw_y = offset_y + (by - min_y) as f32 * tile_size;
         draw_snes_block(draw_x, draw_y, tile_size, tetromino.color);
     }
@@ -940,16 +4352,397 @@ fn draw_preview(tetromino: &Tetromino, pos_x: f32, pos_y: f32, tile_size: f32) {
 
 #[macroquad::main("Tetris")]
 async fn main() {
-    // Optionally, set the window size:
-    request_new_screen_size(1410.0, 700.0);
     let mut game_state = GameState::new();
+    request_new_screen_size(game_state.config.window_width as f32, game_state.config.window_height as f32);
+    if game_state.config.fullscreen {
+        set_fullscreen(true);
+    }
+    // Closing the window goes through GameState::request_quit so a run isn't lost.
+    prevent_quit();
+    // A replay file passed on the command line starts playing right away.
+    if let Some(playback) = std::env::args().nth(1).and_then(|path| ReplayInput::load(&path)) {
+        game_state.start_replay(playback);
+    }
 
     loop {
-        if is_key_pressed(KeyCode::Space) && !game_state.started {
-            game_state.start_game();
+        if is_quit_requested() {
+            game_state.request_quit();
         }
         game_state.update();
+        // Started after update so the same Space press isn't also read as a sonic drop.
+        if is_key_pressed(KeyCode::Space) && !game_state.started && !game_state.capturing_keys() {
+            game_state.playback = None;
+            game_state.start_game();
+        }
+        if game_state.quit_confirmed {
+            break;
+        }
         game_state.draw();
+        if game_state.show_debug {
+            game_state.draw_debug_overlay();
+        }
+        if is_key_pressed(KeyCode::F12) {
+            save_screenshot();
+        }
         next_frame().await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh Classic game with default settings and a fixed seed, whatever profile is
+    // saved where the tests run.
+    fn new_game() -> GameState {
+        let mut game = GameState::new();
+        game.config = Config::default();
+        game.seed = Some(1);
+        game.start_game();
+        game
+    }
+
+    // One simulated frame where `action` goes down.
+    fn press(action: GameAction) -> InputFrame {
+        let mut input = InputFrame::default();
+        input.pressed[action as usize] = true;
+        input.down[action as usize] = true;
+        input
+    }
+
+    // An empty board, indexed board[y][x] like the game's.
+    fn empty_board(width: usize, height: usize) -> Vec<Vec<Cell>> {
+        vec![vec![None; width]; height]
+    }
+
+    // The board cells a piece covers, sorted so pieces can be compared.
+    fn cells(t: &Tetromino) -> [[i32; 2]; 4] {
+        let mut cells = t.shape.map(|[dx, dy]| [t.pos.0 + dx, t.pos.1 + dy]);
+        cells.sort();
+        cells
+    }
+
+    // What identifies a piece's placement, for comparing before and after.
+    fn placement(t: Option<Tetromino>) -> Option<(TetrominoType, (i32, i32), usize)> {
+        t.map(|t| (t.t_type, t.pos, t.rotation))
+    }
+
+    // Fills a `size` x `size` block with one piece's cells.
+    fn fill(board: &mut [Vec<Cell>], x: usize, y: usize, size: usize, t_type: TetrominoType, id: u32) {
+        for row in &mut board[y..y + size] {
+            for cell in &mut row[x..x + size] {
+                *cell = Some((WHITE, t_type, id));
+            }
+        }
+    }
+
+    #[test]
+    fn input_between_pieces_is_ignored() {
+        let mut game = new_game();
+        game.tetromino = None;
+        for action in GameAction::ALL {
+            game.process_input(&press(action), SIM_STEP);
+        }
+        assert!(game.tetromino.is_none());
+        assert!(game.hold_tetromino.is_none());
+        assert_eq!(game.score, 0);
+    }
+
+    #[test]
+    fn drop_distance_lands_on_the_stack() {
+        let mut game = new_game();
+        let stacks = [
+            vec![],
+            vec![(0, 19), (1, 19), (2, 18), (2, 19), (5, 15), (9, 10)],
+            vec![(3, 12), (4, 12), (5, 12), (6, 12), (4, 17), (4, 18), (4, 19)],
+        ];
+        for stack in stacks {
+            game.board = empty_board(game.width, game.height);
+            for (x, y) in stack {
+                game.board[y][x] = Some((GRAY, TetrominoType::Garbage, 0));
+            }
+            for t_type in [TetrominoType::I, TetrominoType::O, TetrominoType::T, TetrominoType::S, TetrominoType::L] {
+                for x in 0..=6 {
+                    let mut t = Tetromino::new(t_type, ColorScheme::Classic, game.width);
+                    t.pos.0 = x;
+                    let d = game.drop_distance(&t);
+                    assert!(!game.check_collision(&t.shape, (x, t.pos.1 + d)));
+                    assert!(game.check_collision(&t.shape, (x, t.pos.1 + d + 1)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gold_square_scores_without_a_line_clear() {
+        let mut game = new_game();
+        fill(&mut game.board, 0, 16, 2, TetrominoType::O, 101);
+        fill(&mut game.board, 0, 18, 2, TetrominoType::O, 102);
+        fill(&mut game.board, 2, 18, 2, TetrominoType::O, 103);
+        // The last O fills the square's top-right corner.
+        let mut last = Tetromino::new(TetrominoType::O, ColorScheme::Classic, game.width);
+        last.pos = (2, 16);
+        game.tetromino = Some(last);
+        game.lock_tetromino();
+
+        assert!(game.clearing_lines.is_empty());
+        assert_eq!(game.lines_cleared, 0);
+        assert_eq!(game.score, GOLD_POINTS);
+    }
+
+    #[test]
+    fn blink_leaves_square_blocks_as_they_were() {
+        let mut game = new_game();
+        fill(&mut game.board, 0, 16, 2, TetrominoType::O, 101);
+        fill(&mut game.board, 2, 16, 2, TetrominoType::O, 102);
+        fill(&mut game.board, 0, 18, 2, TetrominoType::O, 103);
+        fill(&mut game.board, 2, 18, 2, TetrominoType::O, 104);
+        let before = game.board.clone();
+        game.check_for_4x4_squares();
+        assert_eq!(game.active_squares.len(), 1);
+
+        for _ in 0..1000 {
+            if game.active_squares.is_empty() {
+                break;
+            }
+            game.update_square_effects(SIM_STEP);
+        }
+        assert!(game.active_squares.is_empty());
+        assert_eq!(game.board, before);
+    }
+
+    #[test]
+    fn full_row_is_found_and_removed() {
+        let mut board = empty_board(4, 4);
+        for (x, cell) in board[3].iter_mut().enumerate() {
+            *cell = Some((WHITE, TetrominoType::I, x as u32));
+        }
+        board[2][0] = Some((RED, TetrominoType::T, 9));
+        assert_eq!(full_rows(&board), vec![3]);
+
+        remove_rows(&mut board, &[3]);
+        assert_eq!(board.len(), 4);
+        assert!(full_rows(&board).is_empty());
+        assert!(board[0].iter().all(|cell| cell.is_none()));
+        // The block above the cleared row drops into its place.
+        assert_eq!(board[3][0].map(|(_, t, id)| (t, id)), Some((TetrominoType::T, 9)));
+        assert!(board[2][0].is_none());
+    }
+
+    #[test]
+    fn square_of_one_piece_type_is_gold() {
+        let mut board = empty_board(6, 6);
+        fill(&mut board, 0, 2, 2, TetrominoType::O, 1);
+        fill(&mut board, 2, 2, 2, TetrominoType::O, 2);
+        fill(&mut board, 0, 4, 2, TetrominoType::O, 3);
+        fill(&mut board, 2, 4, 2, TetrominoType::O, 4);
+        let mut squared = HashSet::new();
+        let squares = find_new_squares(&board, &mut squared);
+        assert_eq!(squares.len(), 1);
+        assert_eq!((squares[0].x, squares[0].y), (0, 2));
+        assert!(squares[0].is_gold);
+        // The same pieces never score a second square.
+        assert!(find_new_squares(&board, &mut squared).is_empty());
+    }
+
+    #[test]
+    fn square_of_mixed_piece_types_is_silver() {
+        let mut board = empty_board(6, 6);
+        fill(&mut board, 0, 2, 2, TetrominoType::O, 1);
+        fill(&mut board, 2, 2, 2, TetrominoType::O, 2);
+        fill(&mut board, 0, 4, 2, TetrominoType::T, 3);
+        fill(&mut board, 2, 4, 2, TetrominoType::T, 4);
+        let squares = find_new_squares(&board, &mut HashSet::new());
+        assert_eq!(squares.len(), 1);
+        assert!(!squares[0].is_gold);
+    }
+
+    #[test]
+    fn o_piece_turns_in_place() {
+        let board = empty_board(GRID_WIDTH, GRID_HEIGHT);
+        let o = Tetromino::new(TetrominoType::O, ColorScheme::Classic, GRID_WIDTH);
+        for turned in [rotated(&board, &o, true), rotated(&board, &o, false), rotated_180(&board, &o)] {
+            let turned = turned.unwrap();
+            assert_eq!((cells(&turned), turned.rotation), (cells(&o), o.rotation));
+        }
+    }
+
+    #[test]
+    fn i_piece_turns_about_its_srs_center() {
+        let board = empty_board(GRID_WIDTH, GRID_HEIGHT);
+        let mut i = Tetromino::new(TetrominoType::I, ColorScheme::Classic, GRID_WIDTH);
+        i.pos.1 = 5;
+        let start = cells(&i);
+        // Clockwise from spawn, the I stands in the third column of its box.
+        let r = rotated(&board, &i, true).unwrap();
+        let cols: HashSet<i32> = cells(&r).iter().map(|&[x, _]| x).collect();
+        assert_eq!(cols, HashSet::from([i.pos.0 + 2]));
+        // State 2 lies one row below the spawn row, both ways round.
+        let flat = rotated(&board, &r, true).unwrap();
+        assert!(cells(&flat).iter().all(|&[_, y]| y == i.pos.1 + 1));
+        assert_eq!(cells(&rotated_180(&board, &i).unwrap()), cells(&flat));
+        // Four turns either way come back to the start.
+        for clockwise in [true, false] {
+            let mut t = i;
+            for _ in 0..4 {
+                t = rotated(&board, &t, clockwise).unwrap();
+            }
+            assert_eq!((cells(&t), t.rotation), (start, 0));
+        }
+    }
+
+    #[test]
+    fn blocked_hold_swap_changes_nothing() {
+        let mut game = new_game();
+        game.hold_tetromino = Some(Tetromino::new(TetrominoType::I, ColorScheme::Classic, game.width));
+        if let Some(t) = game.tetromino.as_mut() {
+            t.pos.1 = 10;
+        }
+        // Block the row the held I would come back into.
+        for cell in &mut game.board[0][3..7] {
+            *cell = Some((GRAY, TetrominoType::Garbage, 0));
+        }
+        let (current, held) = (placement(game.tetromino), placement(game.hold_tetromino));
+
+        game.process_input(&press(GameAction::Hold), SIM_STEP);
+        assert_eq!(placement(game.tetromino), current);
+        assert_eq!(placement(game.hold_tetromino), held);
+        assert!(!game.hold_used);
+    }
+
+    #[test]
+    fn blocked_spawn_tops_out() {
+        let mut game = new_game();
+        // A stack reaching the spawn rows leaves the next piece nowhere to go.
+        for row in &mut game.board[..2] {
+            row.fill(Some((GRAY, TetrominoType::Garbage, 0)));
+        }
+        game.spawn_new_tetromino();
+        assert!(game.game_over);
+        assert_eq!(game.end_reason, EndReason::TopOut);
+    }
+
+    #[test]
+    fn finesse_minimum_counts_the_fewest_presses() {
+        let o = Tetromino::new(TetrominoType::O, ColorScheme::Classic, GRID_WIDTH);
+        let mut at_wall = o;
+        at_wall.pos.0 -= o.shape.iter().map(|&[dx, _]| o.pos.0 + dx).min().unwrap();
+        assert_eq!(finesse_minimum(GRID_WIDTH, GRID_HEIGHT, o, &footprint(&at_wall)), Some(1));
+
+        let t = Tetromino::new(TetrominoType::T, ColorScheme::Classic, GRID_WIDTH);
+        assert_eq!(finesse_minimum(GRID_WIDTH, GRID_HEIGHT, t, &footprint(&t)), Some(0));
+    }
+
+    #[test]
+    fn first_hold_brings_in_the_previewed_piece() {
+        let mut game = new_game();
+        let current = game.tetromino.map(|t| t.t_type);
+        let previewed = game.next_queue.front().map(|t| t.t_type);
+        let second = game.next_queue[1].t_type;
+        let queue_len = game.next_queue.len();
+
+        game.process_input(&press(GameAction::Hold), SIM_STEP);
+        assert_eq!(game.hold_tetromino.map(|t| t.t_type), current);
+        assert_eq!(game.tetromino.map(|t| t.t_type), previewed);
+        assert_eq!(game.next_queue.front().map(|t| t.t_type), Some(second));
+        assert_eq!(game.next_queue.len(), queue_len);
+        assert!(game.hold_used);
+    }
+
+    #[test]
+    fn pieces_spawn_centered() {
+        let expected = [
+            (TetrominoType::I, 3..=6),
+            (TetrominoType::O, 4..=5),
+            (TetrominoType::T, 3..=5),
+            (TetrominoType::S, 3..=5),
+            (TetrominoType::Z, 3..=5),
+            (TetrominoType::J, 3..=5),
+            (TetrominoType::L, 3..=5),
+        ];
+        for (t_type, columns) in expected {
+            let t = Tetromino::new(t_type, ColorScheme::Classic, GRID_WIDTH);
+            let occupied: HashSet<i32> = cells(&t).iter().map(|&[x, _]| x).collect();
+            assert_eq!(occupied, columns.collect(), "{:?}", t_type);
+        }
+    }
+
+    #[test]
+    fn format_utc_gives_civil_dates() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_utc(1_700_000_000), "2023-11-14 22:13:20");
+    }
+
+    // Before/after timing for the 4x4 piece check on a near-full board. Run it with
+    // `cargo test --release -- --ignored --nocapture square_scan`.
+    #[test]
+    #[ignore]
+    fn square_scan_benchmark() {
+        // The check as it was: walk the whole board for every candidate region.
+        fn pieces_within_by_rescan(board: &[Vec<Cell>], pieces: &[u32], x: usize, y: usize) -> bool {
+            board.iter().enumerate().all(|(row, cells)| {
+                cells.iter().enumerate().all(|(col, cell)| match cell {
+                    Some((_, _, id)) if pieces.contains(id) => (x..x + 4).contains(&col) && (y..y + 4).contains(&row),
+                    _ => true,
+                })
+            })
+        }
+
+        // 2x2 blocks over the bottom 18 rows, each its own piece.
+        let mut board = empty_board(GRID_WIDTH, GRID_HEIGHT);
+        let mut id = 1;
+        for y in (2..GRID_HEIGHT).step_by(2) {
+            for x in (0..GRID_WIDTH).step_by(2) {
+                fill(&mut board, x, y, 2, TetrominoType::O, id);
+                id += 1;
+            }
+        }
+        let regions: Vec<(usize, usize, Vec<u32>)> = (0..GRID_HEIGHT - 3)
+            .flat_map(|y| (0..GRID_WIDTH - 3).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let mut ids: Vec<u32> = board[y..y + 4].iter().flat_map(|row| &row[x..x + 4]).flatten().map(|cell| cell.2).collect();
+                ids.sort();
+                ids.dedup();
+                (x, y, ids)
+            })
+            .collect();
+
+        const RUNS: u32 = 1000;
+        let start = std::time::Instant::now();
+        let mut before = vec![];
+        for _ in 0..RUNS {
+            before = regions.iter().map(|(x, y, ids)| pieces_within_by_rescan(&board, ids, *x, *y)).collect();
+        }
+        let rescan = start.elapsed() / RUNS;
+        let start = std::time::Instant::now();
+        let mut after = vec![];
+        for _ in 0..RUNS {
+            let bounds = piece_bounds(&board);
+            after = regions.iter().map(|(x, y, ids)| pieces_within(&bounds, ids, *x, *y)).collect();
+        }
+        let bounded = start.elapsed() / RUNS;
+        assert_eq!(before, after);
+        println!("4x4 piece check per scan: {:?} rescanning, {:?} with piece bounds", rescan, bounded);
+    }
+
+    #[test]
+    fn zen_survives_a_blocked_spawn_after_a_clear() {
+        let mut game = GameState::new();
+        game.config = Config::default();
+        game.game_mode = GameMode::Zen;
+        game.start_game();
+        for row in &mut game.board[..4] {
+            for cell in &mut row[3..7] {
+                *cell = Some((GRAY, TetrominoType::Garbage, 0));
+            }
+        }
+        for cell in &mut game.board[19] {
+            *cell = Some((GRAY, TetrominoType::Garbage, 0));
+        }
+        game.clearing_lines = vec![19];
+        game.clear_lines_delayed();
+        assert!(!game.game_over);
+        assert!(game.tetromino.is_some());
+    }
+}